@@ -0,0 +1,148 @@
+use crate::flash::SharedFlash;
+use core::cell::RefCell;
+use defmt::Debug2Format;
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, with_timeout};
+use esp_radio::ble::controller::BleConnector;
+use event_storage::bonds::{Bond, Bonds};
+use event_storage::persist::FlashStore;
+use trouble_host::prelude::*;
+
+/// Bonds live in the `bonds` partition that `partitions.csv` adds after
+/// `config`, in slots of one sector.
+const BONDS_START: u32 = 0x352000;
+const BONDS_SLOT_SIZE: u32 = 0x1000;
+const BONDS_SLOTS: u32 = 2;
+
+/// Centrals remembered at once. Pairing with one more forgets the central
+/// paired longest ago.
+pub const MAX_BONDS: usize = 4;
+const BONDS_LEN: usize = Bonds::<MAX_BONDS>::ENCODED_LEN;
+
+/// How long a pairing waits for the pairing button before it is refused.
+const CONFIRM_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub type BondStore = FlashStore<SharedFlash<'static>>;
+
+pub type BleStack<'a> = Stack<'a, ExternalController<BleConnector<'static>, 1>, DefaultPacketPool>;
+
+pub fn create_store(flash: SharedFlash<'static>) -> BondStore {
+    FlashStore::new(flash, BONDS_START, BONDS_SLOT_SIZE, BONDS_SLOTS).unwrap()
+}
+
+/// Keeps the security manager's bonds and the copy in flash in step, and
+/// lets someone at the beacon confirm new pairings with the pairing button.
+pub struct Bonding<'a, 's> {
+    stack: &'a BleStack<'s>,
+    bonds: RefCell<Bonds<MAX_BONDS>>,
+    store: RefCell<BondStore>,
+    presses: Signal<NoopRawMutex, ()>,
+}
+
+impl<'a, 's> Bonding<'a, 's> {
+    /// Loads the stored bonds, or none if they cannot be read, and hands them
+    /// to the security manager so bonded centrals can encrypt straight away.
+    pub fn load(mut store: BondStore, stack: &'a BleStack<'s>) -> Self {
+        let mut buffer = [0; BONDS_LEN];
+        let bonds = match store.load(&mut buffer) {
+            Ok(Some(len)) => match Bonds::decode(&buffer[..len]) {
+                Ok(bonds) => bonds,
+                Err(e) => {
+                    defmt::warn!("ignoring stored bonds error={:?}", Debug2Format(&e));
+                    Bonds::new()
+                }
+            },
+            Ok(None) => Bonds::new(),
+            Err(e) => {
+                defmt::warn!("could not load bonds error={:?}", Debug2Format(&e));
+                Bonds::new()
+            }
+        };
+
+        for bond in bonds.iter() {
+            if let Err(e) = stack.add_bond_information(bond_information(bond)) {
+                defmt::warn!("could not restore bond error={:?}", Debug2Format(&e));
+            }
+        }
+        defmt::info!("restored bonds count={}", bonds.len());
+
+        Self {
+            stack,
+            bonds: RefCell::new(bonds),
+            store: RefCell::new(store),
+            presses: Signal::new(),
+        }
+    }
+
+    /// Notes a press of the pairing button.
+    pub fn button_pressed(&self) {
+        self.presses.signal(());
+    }
+
+    /// Waits for the pairing button to confirm a pairing, returning whether
+    /// it was pressed in time. Only presses after the call count.
+    pub async fn confirm(&self) -> bool {
+        self.presses.reset();
+        with_timeout(CONFIRM_TIMEOUT, self.presses.wait())
+            .await
+            .is_ok()
+    }
+
+    /// Remembers the bond from a completed pairing. A bond the table has no
+    /// room for pushes out the oldest, which the security manager forgets
+    /// too.
+    pub fn save(&self, information: &BondInformation) {
+        if !information.is_bonded {
+            return;
+        }
+        let bond = bond(information);
+
+        let mut bonds = self.bonds.borrow_mut();
+        if bonds.get(&bond.address) == Some(&bond) {
+            return;
+        }
+        if let Some(evicted) = bonds.insert(bond) {
+            let identity = bond_information(&evicted).identity;
+            if let Err(e) = self.stack.remove_bond_information(identity) {
+                defmt::warn!("could not forget bond error={:?}", Debug2Format(&e));
+            }
+        }
+
+        let mut buffer = [0; BONDS_LEN];
+        match bonds.encode(&mut buffer) {
+            Ok(len) => {
+                if let Err(e) = self.store.borrow_mut().save(&buffer[..len]) {
+                    defmt::warn!("could not save bonds error={:?}", Debug2Format(&e));
+                }
+            }
+            Err(e) => defmt::warn!("could not encode bonds error={:?}", Debug2Format(&e)),
+        }
+    }
+}
+
+fn bond(information: &BondInformation) -> Bond {
+    Bond {
+        address: information.identity.bd_addr.into_inner(),
+        ltk: information.ltk.to_le_bytes(),
+        irk: information.identity.irk.map(|irk| irk.to_le_bytes()),
+        authenticated: information.security_level == SecurityLevel::EncryptedAuthenticated,
+    }
+}
+
+fn bond_information(bond: &Bond) -> BondInformation {
+    let security_level = if bond.authenticated {
+        SecurityLevel::EncryptedAuthenticated
+    } else {
+        SecurityLevel::Encrypted
+    };
+    BondInformation::new(
+        Identity {
+            bd_addr: BdAddr::new(bond.address),
+            irk: bond.irk.map(IdentityResolvingKey::from_le_bytes),
+        },
+        LongTermKey::from_le_bytes(bond.ltk),
+        security_level,
+        true,
+    )
+}
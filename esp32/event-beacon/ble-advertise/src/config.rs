@@ -0,0 +1,275 @@
+use crate::flash::SharedFlash;
+use core::cell::RefCell;
+use defmt::Debug2Format;
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::watch::{Receiver, Watch};
+use embassy_time::Duration;
+use event_storage::persist::FlashStore;
+use event_storage::storage::DEFAULT_BREAKPOINTS;
+
+/// The configuration lives in the `config` partition that `partitions.csv`
+/// adds after `history`, in slots of one sector.
+const CONFIG_START: u32 = 0x350000;
+const CONFIG_SLOT_SIZE: u32 = 0x1000;
+const CONFIG_SLOTS: u32 = 2;
+
+const CONFIG_VERSION: u8 = 1;
+const CONFIG_LEN: usize = 1 + Setting::ALL_LEN;
+
+/// Longest device name that still fits in the advertising data.
+pub const NAME_MAX: usize = 16;
+pub const WINDOWS: usize = DEFAULT_BREAKPOINTS.len();
+
+pub const SAMPLE_PERIOD_LEN: usize = 2;
+pub const LED_COLOUR_LEN: usize = 3;
+pub const BREAKPOINTS_LEN: usize = 2 * WINDOWS;
+
+const SAMPLE_PERIOD_MS: core::ops::RangeInclusive<u16> = 10..=10_000;
+
+/// Tasks that can wait on configuration changes at the same time.
+const SETTINGS_RECEIVERS: usize = 2;
+
+pub type ConfigStore = FlashStore<SharedFlash<'static>>;
+
+pub type SettingsReceiver<'a> = Receiver<'a, NoopRawMutex, Config, SETTINGS_RECEIVERS>;
+
+pub fn create_store(flash: SharedFlash<'static>) -> ConfigStore {
+    FlashStore::new(flash, CONFIG_START, CONFIG_SLOT_SIZE, CONFIG_SLOTS).unwrap()
+}
+
+/// One configurable value. Each is stored, and carried by its GATT
+/// characteristic, as a fixed number of bytes with integers big-endian.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Setting {
+    /// Milliseconds between samples, a `u16` from 10 to 10000.
+    SamplePeriod,
+    /// Red, green and blue levels of the activity LED.
+    LedColour,
+    /// UTF-8 device name of 1 to [`NAME_MAX`] bytes, padded with zeros.
+    DeviceName,
+    /// Window lengths in seconds, one strictly increasing `u16` per window.
+    Breakpoints,
+}
+
+impl Setting {
+    pub const ALL: [Setting; 4] = [
+        Setting::SamplePeriod,
+        Setting::LedColour,
+        Setting::DeviceName,
+        Setting::Breakpoints,
+    ];
+    const ALL_LEN: usize = SAMPLE_PERIOD_LEN + LED_COLOUR_LEN + NAME_MAX + BREAKPOINTS_LEN;
+
+    pub const fn len(self) -> usize {
+        match self {
+            Setting::SamplePeriod => SAMPLE_PERIOD_LEN,
+            Setting::LedColour => LED_COLOUR_LEN,
+            Setting::DeviceName => NAME_MAX,
+            Setting::Breakpoints => BREAKPOINTS_LEN,
+        }
+    }
+}
+
+/// A value that is out of range or has the wrong length.
+#[derive(Clone, Copy, Debug, defmt::Format)]
+pub struct InvalidSetting(pub Setting);
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Config {
+    sample_period_ms: u16,
+    led_colour: [u8; LED_COLOUR_LEN],
+    name: [u8; NAME_MAX],
+    name_len: usize,
+    breakpoint_secs: [u16; WINDOWS],
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let mut name = [0; NAME_MAX];
+        name[..9].copy_from_slice(b"ESPBeacon");
+
+        Self {
+            sample_period_ms: 100,
+            led_colour: [10, 0, 0],
+            name,
+            name_len: 9,
+            breakpoint_secs: DEFAULT_BREAKPOINTS.map(|breakpoint| (breakpoint / 1000) as u16),
+        }
+    }
+}
+
+impl Config {
+    pub fn sample_period(&self) -> Duration {
+        Duration::from_millis(self.sample_period_ms as u64)
+    }
+
+    pub fn led_colour(&self) -> (u8, u8, u8) {
+        let [r, g, b] = self.led_colour;
+        (r, g, b)
+    }
+
+    pub fn device_name(&self) -> &[u8] {
+        &self.name[..self.name_len]
+    }
+
+    /// Window lengths in milliseconds, as `Events` takes them.
+    pub fn breakpoints(&self) -> [u64; WINDOWS] {
+        self.breakpoint_secs.map(|secs| secs as u64 * 1000)
+    }
+
+    /// Writes the value of `setting` into `bytes`, which must be exactly
+    /// [`Setting::len`] bytes long.
+    pub fn encode_setting(&self, setting: Setting, bytes: &mut [u8]) {
+        match setting {
+            Setting::SamplePeriod => bytes.copy_from_slice(&self.sample_period_ms.to_be_bytes()),
+            Setting::LedColour => bytes.copy_from_slice(&self.led_colour),
+            Setting::DeviceName => bytes.copy_from_slice(&self.name),
+            Setting::Breakpoints => {
+                for (chunk, secs) in bytes.chunks_exact_mut(2).zip(self.breakpoint_secs) {
+                    chunk.copy_from_slice(&secs.to_be_bytes());
+                }
+            }
+        }
+    }
+
+    /// Replaces `setting` with `value`, leaving the configuration unchanged
+    /// if the value is invalid.
+    pub fn set(&mut self, setting: Setting, value: &[u8]) -> Result<(), InvalidSetting> {
+        let invalid = InvalidSetting(setting);
+        match setting {
+            Setting::SamplePeriod => {
+                let value: [u8; SAMPLE_PERIOD_LEN] = value.try_into().map_err(|_| invalid)?;
+                let period = u16::from_be_bytes(value);
+                if !SAMPLE_PERIOD_MS.contains(&period) {
+                    return Err(invalid);
+                }
+                self.sample_period_ms = period;
+            }
+            Setting::LedColour => {
+                self.led_colour = value.try_into().map_err(|_| invalid)?;
+            }
+            Setting::DeviceName => {
+                // A read value comes back padded, so trailing zeros are not
+                // part of the name
+                let len = value
+                    .iter()
+                    .rposition(|&byte| byte != 0)
+                    .map_or(0, |last| last + 1);
+                let name = &value[..len];
+                if name.is_empty()
+                    || value.len() > NAME_MAX
+                    || name.contains(&0)
+                    || core::str::from_utf8(name).is_err()
+                {
+                    return Err(invalid);
+                }
+                self.name = [0; NAME_MAX];
+                self.name[..len].copy_from_slice(name);
+                self.name_len = len;
+            }
+            Setting::Breakpoints => {
+                if value.len() != BREAKPOINTS_LEN {
+                    return Err(invalid);
+                }
+                let mut breakpoint_secs = [0; WINDOWS];
+                for (secs, chunk) in breakpoint_secs.iter_mut().zip(value.chunks_exact(2)) {
+                    *secs = u16::from_be_bytes([chunk[0], chunk[1]]);
+                }
+                let increasing = breakpoint_secs.windows(2).all(|pair| pair[0] < pair[1]);
+                if breakpoint_secs[0] == 0 || !increasing {
+                    return Err(invalid);
+                }
+                self.breakpoint_secs = breakpoint_secs;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// A version byte followed by every setting in [`Setting::ALL`] order.
+    fn encode(&self) -> [u8; CONFIG_LEN] {
+        let mut bytes = [0; CONFIG_LEN];
+        bytes[0] = CONFIG_VERSION;
+        let mut offset = 1;
+        for setting in Setting::ALL {
+            self.encode_setting(setting, &mut bytes[offset..offset + setting.len()]);
+            offset += setting.len();
+        }
+        bytes
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, InvalidSetting> {
+        let mut config = Config::default();
+        let mut offset = 1;
+        for setting in Setting::ALL {
+            let value = bytes
+                .get(offset..offset + setting.len())
+                .ok_or(InvalidSetting(setting))?;
+            config.set(setting, value)?;
+            offset += setting.len();
+        }
+        Ok(config)
+    }
+}
+
+/// The live configuration and where it is stored.
+pub struct Settings {
+    current: Watch<NoopRawMutex, Config, SETTINGS_RECEIVERS>,
+    store: RefCell<ConfigStore>,
+}
+
+impl Settings {
+    /// Loads the stored configuration, or the defaults if there is none or
+    /// it cannot be read.
+    pub fn load(mut store: ConfigStore) -> Self {
+        let mut buffer = [0; CONFIG_LEN];
+        let config = match store.load(&mut buffer) {
+            Ok(Some(len)) if buffer[0] == CONFIG_VERSION => match Config::decode(&buffer[..len]) {
+                Ok(config) => config,
+                Err(e) => {
+                    defmt::warn!("ignoring stored configuration error={}", e);
+                    Config::default()
+                }
+            },
+            Ok(Some(_)) => {
+                defmt::warn!("ignoring stored configuration of another version");
+                Config::default()
+            }
+            Ok(None) => Config::default(),
+            Err(e) => {
+                defmt::warn!("could not load configuration error={:?}", Debug2Format(&e));
+                Config::default()
+            }
+        };
+
+        Self {
+            current: Watch::new_with(config),
+            store: RefCell::new(store),
+        }
+    }
+
+    pub fn get(&self) -> Config {
+        self.current.try_get().unwrap_or_default()
+    }
+
+    /// Waits on configuration changes. Only [`SETTINGS_RECEIVERS`] receivers
+    /// can exist at once.
+    pub fn receiver(&self) -> Option<SettingsReceiver<'_>> {
+        self.current.receiver()
+    }
+
+    /// Validates and applies one setting, then saves the whole configuration
+    /// and tells every receiver. A failed save still applies the setting
+    /// until the next reboot.
+    pub fn update(&self, setting: Setting, value: &[u8]) -> Result<Config, InvalidSetting> {
+        let mut config = self.get();
+        config.set(setting, value)?;
+
+        if let Err(e) = self.store.borrow_mut().save(&config.encode()) {
+            defmt::warn!("could not save configuration error={:?}", Debug2Format(&e));
+        }
+        self.current.sender().send(config);
+
+        Ok(config)
+    }
+}
@@ -0,0 +1,38 @@
+use core::cell::RefCell;
+use embedded_storage::nor_flash::{ErrorType, NorFlash, ReadNorFlash};
+use esp_storage::FlashStorage;
+
+/// Lets the snapshot and configuration stores share the one flash driver.
+/// Every access finishes before it returns, so the borrow never spans an
+/// await point.
+#[derive(Clone, Copy)]
+pub struct SharedFlash<'a>(pub &'a RefCell<FlashStorage<'static>>);
+
+impl ErrorType for SharedFlash<'_> {
+    type Error = <FlashStorage<'static> as ErrorType>::Error;
+}
+
+impl ReadNorFlash for SharedFlash<'_> {
+    const READ_SIZE: usize = <FlashStorage<'static> as ReadNorFlash>::READ_SIZE;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        self.0.borrow_mut().read(offset, bytes)
+    }
+
+    fn capacity(&self) -> usize {
+        self.0.borrow().capacity()
+    }
+}
+
+impl NorFlash for SharedFlash<'_> {
+    const WRITE_SIZE: usize = <FlashStorage<'static> as NorFlash>::WRITE_SIZE;
+    const ERASE_SIZE: usize = <FlashStorage<'static> as NorFlash>::ERASE_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        self.0.borrow_mut().erase(from, to)
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.0.borrow_mut().write(offset, bytes)
+    }
+}
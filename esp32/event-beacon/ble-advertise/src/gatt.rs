@@ -1,109 +1,786 @@
+use crate::bonding::Bonding;
+use crate::config::{
+    BREAKPOINTS_LEN, Config, LED_COLOUR_LEN, NAME_MAX, SAMPLE_PERIOD_LEN, Setting, Settings,
+    SettingsReceiver, WINDOWS,
+};
 use anyhow::{Result, anyhow};
+use core::future::pending;
+use defmt::Debug2Format;
+use embassy_futures::join::join_array;
+use embassy_futures::select::{Either, select};
 use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_time::{Duration, Ticker, Timer};
 use esp_radio::ble::controller::BleConnector;
-use event_storage::storage::Events;
+use event_storage::clock::unix_ms_from_utc;
+use event_storage::occupancy::State;
+use event_storage::storage::{DEFAULT_CAPACITY, Events};
+use event_storage::updates::ReportReceiver;
 use trouble_host::prelude::Uuid;
 use trouble_host::prelude::*;
 
-pub async fn advertise_and_handle_connection(
+/// Report and Current Time services, plus a configuration service with two
+/// attributes for each setting.
+const ATTRIBUTES: usize = 16;
+
+type Server<'a, P> = AttributeServer<'a, NoopRawMutex, P, ATTRIBUTES, 1, 1>;
+
+/// When a subscribed central gets a fresh report.
+#[derive(Clone, Copy)]
+pub enum Notifications {
+    /// Whenever a bucket count changes.
+    OnChange,
+    /// On a fixed interval, whether or not anything changed.
+    Every(Duration),
+}
+
+/// Link security a characteristic needs before a central may read or write
+/// it. A central refused for want of security pairs and tries again.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Access {
+    Open,
+    Encrypted,
+    /// Encrypted with keys from a pairing that someone at the beacon
+    /// confirmed with the pairing button.
+    Authenticated,
+}
+
+impl Access {
+    /// The error to refuse a link at `level` with, if it falls short.
+    fn refusal(self, level: SecurityLevel) -> Option<AttErrorCode> {
+        match (self, level) {
+            (Access::Open, _)
+            | (Access::Encrypted, SecurityLevel::Encrypted)
+            | (_, SecurityLevel::EncryptedAuthenticated) => None,
+            (Access::Encrypted, _) => Some(AttErrorCode::INSUFFICIENT_ENCRYPTION),
+            (Access::Authenticated, _) => Some(AttErrorCode::INSUFFICIENT_AUTHENTICATION),
+        }
+    }
+}
+
+/// The report is broadcast in every advertisement anyway. The clock dates
+/// everything exported, so setting it takes a paired link. Any central in
+/// range can pair without authentication, so changing the configuration
+/// takes a pairing confirmed at the beacon itself.
+const REPORT_ACCESS: Access = Access::Open;
+const CURRENT_TIME_ACCESS: Access = Access::Encrypted;
+const CONFIG_ACCESS: Access = Access::Authenticated;
+
+/// Length of the report characteristic, in the versioned format of
+/// [`event_storage::report`] with every section. Centrals read it with long
+/// reads, but a notification must fit in one packet, so only centrals that
+/// negotiated an ATT MTU of at least `REPORT_LEN + 3` get notified.
+const REPORT_LEN: usize = Events::<8, DEFAULT_CAPACITY>::REPORT_LEN;
+
+/// Identifies the beacon in its reports. There is only the one beacon.
+const DEVICE_ID: u16 = 0;
+
+/// Centrals that can be connected at the same time, each with its own
+/// attribute server and subscription state.
+pub const MAX_CONNECTIONS: usize = 2;
+
+type Peripheral<'a> = trouble_host::prelude::Peripheral<
+    'a,
+    ExternalController<BleConnector<'a>, 1>,
+    DefaultPacketPool,
+>;
+
+pub async fn advertise_and_handle_connections(
     events: &Events,
-    peripheral: &mut Peripheral<'_, ExternalController<BleConnector<'_>, 1>, DefaultPacketPool>,
+    settings: &Settings,
+    bonding: &Bonding<'_, '_>,
+    notifications: Notifications,
+    peripheral: &mut Peripheral<'_>,
 ) -> Result<()> {
-    let mut advertising_data: [u8; 32] = [0; 32];
-
-    let encoded_advertising_data_length = AdStructure::encode_slice(
-        &[
-            AdStructure::Flags(LE_GENERAL_DISCOVERABLE),
-            AdStructure::ServiceUuids16(&[[0, 0]]),
-            AdStructure::CompleteLocalName("ESPBeacon".as_bytes()),
-        ],
-        &mut advertising_data,
+    // One token per connection slot that is free to serve a central
+    let free_slots: Channel<NoopRawMutex, (), MAX_CONNECTIONS> = Channel::new();
+    for _ in 0..MAX_CONNECTIONS {
+        let _ = free_slots.try_send(());
+    }
+    let connections: Channel<NoopRawMutex, Connection<'_, DefaultPacketPool>, MAX_CONNECTIONS> =
+        Channel::new();
+
+    let handlers = join_array(core::array::from_fn::<_, MAX_CONNECTIONS, _>(|slot| {
+        handle_connections(
+            slot,
+            &connections,
+            &free_slots,
+            events,
+            settings,
+            bonding,
+            notifications,
+        )
+    }));
+    match select(
+        advertise(events, settings, peripheral, &free_slots, &connections),
+        handlers,
     )
-    .map_err(|e| anyhow!("could not encode advertising data error={:?}", e))?;
+    .await
+    {
+        Either::First(result) => result,
+        Either::Second(_) => Ok(()),
+    }
+}
 
-    defmt::debug!(
-        "encoded_advertising_data_length={}",
-        encoded_advertising_data_length
-    );
+/// Advertises for as long as the beacon runs. Advertising is connectable
+/// while a connection slot is free and continues as a plain broadcast while
+/// every slot is taken.
+async fn advertise<'a>(
+    events: &Events,
+    settings: &Settings,
+    peripheral: &mut Peripheral<'a>,
+    free_slots: &Channel<NoopRawMutex, (), MAX_CONNECTIONS>,
+    connections: &Channel<NoopRawMutex, Connection<'a, DefaultPacketPool>, MAX_CONNECTIONS>,
+) -> Result<()> {
+    let mut reports = events
+        .report_receiver()
+        .map_err(|e| anyhow!("could not receive reports error={:?}", e))?;
+    let mut changes = settings
+        .receiver()
+        .ok_or_else(|| anyhow!("no settings receiver left for advertising"))?;
 
     loop {
+        let connectable = free_slots.try_receive().is_ok();
+
+        let broadcast = encode_broadcast(events).await;
+        let config = changes.try_get().unwrap_or_default();
+        let data = AdvertisingData::new(&broadcast, &config)?;
         let advertiser = peripheral
             .advertise(
                 &AdvertisementParameters::default(),
-                Advertisement::ConnectableScannableUndirected {
-                    adv_data: &advertising_data,
-                    scan_data: &[],
-                },
+                data.advertisement(connectable),
             )
             .await
             .map_err(|e| anyhow!("could not start advertising error={:?}", e))?;
 
-        let connection = advertiser
-            .accept()
+        let refresh = refresh_advertisement(
+            events,
+            peripheral,
+            &mut reports,
+            &mut changes,
+            connectable,
+            broadcast,
+            data,
+        );
+        if !connectable {
+            // Start over as connectable once a central disconnects
+            select(free_slots.ready_to_receive(), refresh).await;
+            continue;
+        }
+        let Either::First(connection) = select(advertiser.accept(), refresh).await;
+        let connection =
+            connection.map_err(|e| anyhow!("could not connect to central error={:?}", e))?;
+        connections.send(connection).await;
+    }
+}
+
+/// Keeps the running advertisement current. The latest report is swapped in
+/// at most once per refresh interval, and the scan response is rebuilt as
+/// soon as the settings change, so a new name shows straight away.
+async fn refresh_advertisement(
+    events: &Events,
+    peripheral: &mut Peripheral<'_>,
+    reports: &mut ReportReceiver<'_, WINDOWS>,
+    changes: &mut SettingsReceiver<'_>,
+    connectable: bool,
+    mut broadcast: [u8; BROADCAST_LEN],
+    mut data: AdvertisingData,
+) -> ! {
+    loop {
+        let encoded = match select(reports.changed(), changes.changed()).await {
+            Either::First(_) => {
+                let latest = encode_broadcast(events).await;
+                if latest == broadcast {
+                    continue;
+                }
+                broadcast = latest;
+                data.set_broadcast(&broadcast)
+            }
+            Either::Second(config) => data.set_config(&config),
+        };
+        if let Err(e) = encoded {
+            defmt::warn!("{:?}", Debug2Format(&e));
+            continue;
+        }
+
+        if let Err(e) = peripheral
+            .update_adv_data(data.advertisement(connectable))
             .await
-            .map_err(|e| anyhow!("could not connect to central error={:?}", e))?;
+        {
+            defmt::warn!(
+                "could not update advertising data error={:?}",
+                Debug2Format(&e)
+            );
+        }
+        Timer::after(BROADCAST_REFRESH).await;
+    }
+}
 
-        upgrade_connection_and_handle_events(connection, events).await?
+/// The advertising data and scan response, as last encoded. The advertising
+/// data carries the broadcast and leaves the name and service list to the
+/// scan response, so the report fits.
+struct AdvertisingData {
+    advertising: [u8; 31],
+    advertising_len: usize,
+    scan: [u8; 31],
+    scan_len: usize,
+}
+
+impl AdvertisingData {
+    fn new(broadcast: &[u8; BROADCAST_LEN], config: &Config) -> Result<Self> {
+        let mut data = Self {
+            advertising: [0; 31],
+            advertising_len: 0,
+            scan: [0; 31],
+            scan_len: 0,
+        };
+        data.set_broadcast(broadcast)?;
+        data.set_config(config)?;
+        Ok(data)
+    }
+
+    fn set_broadcast(&mut self, broadcast: &[u8; BROADCAST_LEN]) -> Result<()> {
+        self.advertising_len = AdStructure::encode_slice(
+            &[
+                AdStructure::Flags(LE_GENERAL_DISCOVERABLE),
+                AdStructure::ManufacturerSpecificData {
+                    company_identifier: COMPANY_ID,
+                    payload: broadcast,
+                },
+            ],
+            &mut self.advertising,
+        )
+        .map_err(|e| anyhow!("could not encode advertising data error={:?}", e))?;
+        Ok(())
+    }
+
+    fn set_config(&mut self, config: &Config) -> Result<()> {
+        self.scan_len = AdStructure::encode_slice(
+            &[
+                AdStructure::ServiceUuids16(&[[0, 0]]),
+                AdStructure::CompleteLocalName(config.device_name()),
+            ],
+            &mut self.scan,
+        )
+        .map_err(|e| anyhow!("could not encode scan response error={:?}", e))?;
+        Ok(())
+    }
+
+    fn advertisement(&self, connectable: bool) -> Advertisement<'_> {
+        let adv_data = &self.advertising[..self.advertising_len];
+        let scan_data = &self.scan[..self.scan_len];
+        if connectable {
+            Advertisement::ConnectableScannableUndirected {
+                adv_data,
+                scan_data,
+            }
+        } else {
+            Advertisement::NonconnectableScannableUndirected {
+                adv_data,
+                scan_data,
+            }
+        }
+    }
+}
+
+/// Serves the connections handed to one slot, one at a time, and frees the
+/// slot again after each disconnects.
+async fn handle_connections(
+    slot: usize,
+    connections: &Channel<NoopRawMutex, Connection<'_, DefaultPacketPool>, MAX_CONNECTIONS>,
+    free_slots: &Channel<NoopRawMutex, (), MAX_CONNECTIONS>,
+    events: &Events,
+    settings: &Settings,
+    bonding: &Bonding<'_, '_>,
+    notifications: Notifications,
+) -> ! {
+    loop {
+        let connection = connections.receive().await;
+        defmt::info!("central connected slot={}", slot);
+
+        // A failing connection only ends that connection
+        if let Err(e) = upgrade_connection_and_handle_events(
+            connection,
+            events,
+            settings,
+            bonding,
+            notifications,
+        )
+        .await
+        {
+            defmt::warn!(
+                "connection ended slot={} error={:?}",
+                slot,
+                Debug2Format(&e)
+            );
+        }
+
+        defmt::info!("central disconnected slot={}", slot);
+        free_slots.send(()).await;
     }
 }
 
+/// Shortest time between two updates of the advertised report.
+const BROADCAST_REFRESH: Duration = Duration::from_secs(1);
+
+/// Version of the broadcast layout, bumped whenever it changes.
+const BROADCAST_VERSION: u8 = 1;
+
+/// Company identifier the broadcast is filed under. 0xFFFF is the one the
+/// Bluetooth SIG sets aside for testing, so it is not claimed by anyone.
+const COMPANY_ID: u16 = 0xffff;
+
+/// Payload carried in every advertisement. The full report does not fit in
+/// an advertisement, so the broadcast carries only the counts. It goes out as
+/// a Manufacturer Specific Data structure, after the flags:
+///
+/// | offset | size | field                                              |
+/// |--------|------|----------------------------------------------------|
+/// | 0      | 1    | structure length, 21                               |
+/// | 1      | 1    | type, 0xff for Manufacturer Specific Data          |
+/// | 2      | 2    | [`COMPANY_ID`], little-endian as the spec has it   |
+/// | 4      | 1    | version, [`BROADCAST_VERSION`]                     |
+/// | 5      | 1    | classified state, as [`State::as_byte`] gives it   |
+/// | 6      | 16   | High count of each window, big-endian `u16` each   |
+///
+/// Scanners should match both the company identifier and the version before
+/// trusting the rest. Window lengths are left out, as centrals can read them
+/// from the report characteristic. With the 3 byte flags structure, the
+/// advertisement takes 25 of its 31 bytes.
+const BROADCAST_LEN: usize = 2 + 16;
+
+async fn encode_broadcast(events: &Events) -> [u8; BROADCAST_LEN] {
+    let mut broadcast = [0; BROADCAST_LEN];
+    broadcast[0] = BROADCAST_VERSION;
+    broadcast[1] = current_state(events).await.as_byte();
+    let _result = events.write_bytes(&mut broadcast[2..]).await;
+    broadcast
+}
+
+/// The classified state of the input, Idle until the first sample.
+async fn current_state(events: &Events) -> State {
+    events
+        .classification()
+        .await
+        .map_or(State::Idle, |classification| classification.state)
+}
+
 async fn upgrade_connection_and_handle_events<P: PacketPool>(
     connection: Connection<'_, P>,
     events: &Events,
+    settings: &Settings,
+    bonding: &Bonding<'_, '_>,
+    notifications: Notifications,
 ) -> Result<()> {
-    let mut characteristic_storage: [u8; 16] = [0; 16];
-    let mut table: AttributeTable<'_, NoopRawMutex, 4> = AttributeTable::new();
+    // Bonded centrals get their keys back from flash after a reset
+    if let Err(e) = connection.set_bondable(true) {
+        defmt::warn!("could not allow bonding error={:?}", Debug2Format(&e));
+    }
+
+    let mut characteristic_storage: [u8; REPORT_LEN] = [0; REPORT_LEN];
+    let mut current_time_storage: [u8; CURRENT_TIME_LEN] = [0; CURRENT_TIME_LEN];
+    let config = settings.get();
+    let mut sample_period_storage =
+        setting_value::<SAMPLE_PERIOD_LEN>(&config, Setting::SamplePeriod);
+    let mut led_colour_storage = setting_value::<LED_COLOUR_LEN>(&config, Setting::LedColour);
+    let mut device_name_storage = setting_value::<NAME_MAX>(&config, Setting::DeviceName);
+    let mut breakpoints_storage = setting_value::<BREAKPOINTS_LEN>(&config, Setting::Breakpoints);
+    let mut table: AttributeTable<'_, NoopRawMutex, ATTRIBUTES> = AttributeTable::new();
 
     let service = Service::new(Uuid::new_short(0));
     let mut service_builder = table.add_service(service);
 
     let characteristic_builder = service_builder.add_characteristic(
         Uuid::new_short(1),
-        &[CharacteristicProp::Notify],
+        &[CharacteristicProp::Read, CharacteristicProp::Notify],
         characteristic_storage,
         &mut characteristic_storage,
     );
-    let characteristic_handle = characteristic_builder.build();
+    let report_handle = characteristic_builder.build();
     let _service_handle = service_builder.build();
-    let server: AttributeServer<'_, NoopRawMutex, P, 4, 1, 1> = AttributeServer::new(table);
+
+    // Current Time Service, so a central can give the samples wall-clock time
+    let mut time_service_builder = table.add_service(Service::new(Uuid::new_short(0x1805)));
+    let current_time_handle = time_service_builder
+        .add_characteristic(
+            Uuid::new_short(0x2a2b),
+            &[CharacteristicProp::Write],
+            current_time_storage,
+            &mut current_time_storage,
+        )
+        .build();
+    let _time_service_handle = time_service_builder.build();
+
+    // Configuration service, with one readable and writable characteristic
+    // per setting, laid out as `Setting` describes
+    let mut config_service_builder = table.add_service(Service::new(Uuid::new_short(2)));
+    let properties = [CharacteristicProp::Read, CharacteristicProp::Write];
+    let config_handles = ConfigCharacteristics {
+        sample_period: config_service_builder
+            .add_characteristic(
+                Uuid::new_short(3),
+                &properties,
+                sample_period_storage,
+                &mut sample_period_storage,
+            )
+            .build(),
+        led_colour: config_service_builder
+            .add_characteristic(
+                Uuid::new_short(4),
+                &properties,
+                led_colour_storage,
+                &mut led_colour_storage,
+            )
+            .build(),
+        device_name: config_service_builder
+            .add_characteristic(
+                Uuid::new_short(5),
+                &properties,
+                device_name_storage,
+                &mut device_name_storage,
+            )
+            .build(),
+        breakpoints: config_service_builder
+            .add_characteristic(
+                Uuid::new_short(6),
+                &properties,
+                breakpoints_storage,
+                &mut breakpoints_storage,
+            )
+            .build(),
+    };
+    let _config_service_handle = config_service_builder.build();
+
+    let server: Server<'_, P> = AttributeServer::new(table);
     let upgraded_connection = connection
         .with_attribute_server(&server)
         .map_err(|e| anyhow!("could not upgrade connection error={:?}", e))?;
 
-    gatt_events_task(&upgraded_connection, characteristic_handle, events).await?;
+    gatt_events_task(
+        &server,
+        &upgraded_connection,
+        report_handle,
+        current_time_handle,
+        &config_handles,
+        events,
+        settings,
+        bonding,
+        notifications,
+    )
+    .await?;
 
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn gatt_events_task<P: PacketPool>(
+    server: &Server<'_, P>,
     connection: &GattConnection<'_, '_, P>,
-    characteristic_handle: Characteristic<[u8; 16]>,
+    report_handle: Characteristic<[u8; REPORT_LEN]>,
+    current_time_handle: Characteristic<[u8; CURRENT_TIME_LEN]>,
+    config_handles: &ConfigCharacteristics,
     events: &Events,
+    settings: &Settings,
+    bonding: &Bonding<'_, '_>,
+    notifications: Notifications,
 ) -> Result<()> {
+    // Only counts that moved are worth a notification, not every sample
+    let mut reports = events
+        .report_receiver()
+        .map_err(|e| anyhow!("could not receive reports error={:?}", e))?
+        .with_min_delta(1);
+    let mut ticker = match notifications {
+        Notifications::OnChange => None,
+        Notifications::Every(interval) => Some(Ticker::every(interval)),
+    };
+    // Notifications start disabled on every connection, as the CCCD says
+    let mut subscribed = false;
+    let access = |handle: u16| {
+        if handle == report_handle.handle || Some(handle) == report_handle.cccd_handle {
+            REPORT_ACCESS
+        } else if handle == current_time_handle.handle {
+            CURRENT_TIME_ACCESS
+        } else if config_handles.setting(handle).is_some() {
+            CONFIG_ACCESS
+        } else {
+            Access::Open
+        }
+    };
+
     let _reason = loop {
-        match connection.next().await {
+        let report_due = async {
+            if !subscribed {
+                return pending().await;
+            }
+            match ticker.as_mut() {
+                Some(ticker) => ticker.next().await,
+                None => {
+                    reports.changed().await;
+                }
+            }
+        };
+        let event = match select(connection.next(), report_due).await {
+            Either::First(event) => event,
+            Either::Second(()) => {
+                notify_report(connection, &report_handle, events).await;
+                continue;
+            }
+        };
+
+        match event {
             GattConnectionEvent::Disconnected { reason } => break reason,
-            GattConnectionEvent::Gatt { event } => match event {
-                GattEvent::Write(write_event) => {
-                    let should_notify = write_event.data().len() == 2
-                        && u16::from_le_bytes([write_event.data()[0], write_event.data()[1]])
-                            == 0x0001;
-
-                    let reply = write_event
-                        .accept()
-                        .map_err(|e| anyhow!("could not accept write event error={:?}", e))?;
+            GattConnectionEvent::PassKeyDisplay(key) => {
+                // Only centrals that can type a passkey but not show one ask
+                // for this, and the debug log is all the beacon has to show it
+                defmt::info!("pairing passkey={:06}", key.value());
+            }
+            GattConnectionEvent::PassKeyConfirm(key) => {
+                // Pressing the button stands in for comparing the numbers,
+                // which the beacon cannot show
+                defmt::info!(
+                    "press the pairing button to pair passkey={:06}",
+                    key.value()
+                );
+                let confirmed = bonding.confirm().await;
+                let result = if confirmed {
+                    connection.raw().pass_key_confirm()
+                } else {
+                    defmt::info!("pairing not confirmed in time");
+                    connection.raw().pass_key_cancel()
+                };
+                if let Err(e) = result {
+                    defmt::warn!("could not answer pairing error={:?}", Debug2Format(&e));
+                }
+            }
+            GattConnectionEvent::PairingComplete {
+                security_level,
+                bond,
+            } => {
+                defmt::info!("paired security_level={:?}", Debug2Format(&security_level));
+                if let Some(bond) = bond {
+                    bonding.save(&bond);
+                }
+            }
+            GattConnectionEvent::PairingFailed(e) => {
+                defmt::warn!("pairing failed error={:?}", Debug2Format(&e));
+            }
+            GattConnectionEvent::Gatt { event } => {
+                let level = connection
+                    .raw()
+                    .security_level()
+                    .unwrap_or(SecurityLevel::NoEncryption);
+                let refusal = match &event {
+                    GattEvent::Read(read_event) => access(read_event.handle()).refusal(level),
+                    GattEvent::Write(write_event) => access(write_event.handle()).refusal(level),
+                    _ => None,
+                };
+                if let Some(code) = refusal {
+                    defmt::info!("refusing access over a link without enough security");
+                    let reply = event
+                        .reject(code)
+                        .map_err(|e| anyhow!("could not reject gatt event error={:?}", e))?;
                     reply.send().await;
+                    continue;
+                }
 
-                    if should_notify {
-                        let bytes = events.as_bytes().await;
-                        let _result = characteristic_handle.notify(connection, &bytes).await;
+                match event {
+                    GattEvent::Read(read_event) if read_event.handle() == report_handle.handle => {
+                        // Serve the report as it is now rather than as last notified
+                        if let Some(report) = encode_report(events).await {
+                            let _result = report_handle.set(server, &report);
+                        }
+
+                        let reply = read_event
+                            .accept()
+                            .map_err(|e| anyhow!("could not accept read event error={:?}", e))?;
+                        reply.send().await;
+                    }
+                    GattEvent::Write(write_event)
+                        if Some(write_event.handle()) == report_handle.cccd_handle =>
+                    {
+                        let data = write_event.data();
+                        let enabled =
+                            data.len() == 2 && u16::from_le_bytes([data[0], data[1]]) & 0x0001 != 0;
+
+                        let reply = write_event
+                            .accept()
+                            .map_err(|e| anyhow!("could not accept write event error={:?}", e))?;
+                        reply.send().await;
+
+                        if enabled && !subscribed {
+                            defmt::debug!("report notifications enabled");
+                            if let Some(ticker) = ticker.as_mut() {
+                                ticker.reset();
+                            }
+                            notify_report(connection, &report_handle, events).await;
+                        } else if !enabled && subscribed {
+                            defmt::debug!("report notifications disabled");
+                        }
+                        subscribed = enabled;
+                    }
+                    GattEvent::Write(write_event)
+                        if write_event.handle() == current_time_handle.handle =>
+                    {
+                        let reply = match parse_current_time(write_event.data()) {
+                            Ok(unix_ms) => {
+                                if let Some(correction) = events.set_unix_time(unix_ms).await {
+                                    defmt::info!("corrected clock correction_ms={}", correction);
+                                }
+                                write_event.accept().map_err(|e| {
+                                    anyhow!("could not accept write event error={:?}", e)
+                                })?
+                            }
+                            Err(code) => {
+                                defmt::warn!("rejecting invalid current time");
+                                write_event.reject(code).map_err(|e| {
+                                    anyhow!("could not reject write event error={:?}", e)
+                                })?
+                            }
+                        };
+                        reply.send().await;
                     }
+                    GattEvent::Read(read_event) => {
+                        // Another central may have changed the setting since this
+                        // connection's table was filled in
+                        if let Some(setting) = config_handles.setting(read_event.handle()) {
+                            config_handles.refresh(server, &settings.get(), setting);
+                        }
+                        let reply = read_event
+                            .accept()
+                            .map_err(|e| anyhow!("could not accept read event error={:?}", e))?;
+                        reply.send().await;
+                    }
+                    GattEvent::Write(write_event) => {
+                        let Some(setting) = config_handles.setting(write_event.handle()) else {
+                            let reply = write_event.accept().map_err(|e| {
+                                anyhow!("could not accept write event error={:?}", e)
+                            })?;
+                            reply.send().await;
+                            continue;
+                        };
+
+                        match settings.update(setting, write_event.data()) {
+                            Ok(config) => {
+                                defmt::info!("updated setting={}", setting);
+                                if setting == Setting::Breakpoints {
+                                    events.set_breakpoints(config.breakpoints()).await;
+                                }
+
+                                let reply = write_event.accept().map_err(|e| {
+                                    anyhow!("could not accept write event error={:?}", e)
+                                })?;
+                                reply.send().await;
+                                // A shorter write leaves the end of the old value
+                                // behind, so store the whole value as applied
+                                config_handles.refresh(server, &config, setting);
+                            }
+                            Err(e) => {
+                                defmt::warn!("rejecting invalid setting error={}", e);
+                                let reply = write_event
+                                    .reject(AttErrorCode::VALUE_NOT_ALLOWED)
+                                    .map_err(|e| {
+                                        anyhow!("could not reject write event error={:?}", e)
+                                    })?;
+                                reply.send().await;
+                            }
+                        }
+                    }
+                    _ => {}
                 }
-                _ => {}
-            },
+            }
             _ => {}
         }
     };
 
     Ok(())
 }
+
+/// The configuration service's characteristics, one per [`Setting`].
+struct ConfigCharacteristics {
+    sample_period: Characteristic<[u8; SAMPLE_PERIOD_LEN]>,
+    led_colour: Characteristic<[u8; LED_COLOUR_LEN]>,
+    device_name: Characteristic<[u8; NAME_MAX]>,
+    breakpoints: Characteristic<[u8; BREAKPOINTS_LEN]>,
+}
+
+impl ConfigCharacteristics {
+    fn setting(&self, handle: u16) -> Option<Setting> {
+        match handle {
+            _ if handle == self.sample_period.handle => Some(Setting::SamplePeriod),
+            _ if handle == self.led_colour.handle => Some(Setting::LedColour),
+            _ if handle == self.device_name.handle => Some(Setting::DeviceName),
+            _ if handle == self.breakpoints.handle => Some(Setting::Breakpoints),
+            _ => None,
+        }
+    }
+
+    fn refresh<P: PacketPool>(&self, server: &Server<'_, P>, config: &Config, setting: Setting) {
+        let _result = match setting {
+            Setting::SamplePeriod => self
+                .sample_period
+                .set(server, &setting_value(config, setting)),
+            Setting::LedColour => self.led_colour.set(server, &setting_value(config, setting)),
+            Setting::DeviceName => self
+                .device_name
+                .set(server, &setting_value(config, setting)),
+            Setting::Breakpoints => self
+                .breakpoints
+                .set(server, &setting_value(config, setting)),
+        };
+    }
+}
+
+fn setting_value<const N: usize>(config: &Config, setting: Setting) -> [u8; N] {
+    let mut value = [0; N];
+    config.encode_setting(setting, &mut value);
+    value
+}
+
+async fn encode_report(events: &Events) -> Option<[u8; REPORT_LEN]> {
+    let mut report = [0; REPORT_LEN];
+    match events.encode_report(0, DEVICE_ID, &mut report).await {
+        Ok(_) => Some(report),
+        Err(e) => {
+            defmt::warn!("could not encode report error={:?}", Debug2Format(&e));
+            None
+        }
+    }
+}
+
+/// Notifies the current report, unless the connection's MTU is too small
+/// for it, in which case the central has to read it instead.
+async fn notify_report<P: PacketPool>(
+    connection: &GattConnection<'_, '_, P>,
+    report_handle: &Characteristic<[u8; REPORT_LEN]>,
+    events: &Events,
+) {
+    let mtu = connection.raw().att_mtu() as usize;
+    if mtu < REPORT_LEN + 3 {
+        defmt::debug!("report too long to notify att_mtu={}", mtu);
+        return;
+    }
+    if let Some(report) = encode_report(events).await {
+        let _result = report_handle.notify(connection, &report).await;
+    }
+}
+
+/// Exact Time 256 followed by the adjust reason, as in the Current Time
+/// characteristic.
+const CURRENT_TIME_LEN: usize = 10;
+
+/// Reads a Current Time characteristic value as milliseconds since the Unix
+/// epoch. The characteristic carries UTC time here. A value of any other
+/// length, or one that is not a real date, comes back as the error to reject
+/// the write with.
+fn parse_current_time(data: &[u8]) -> Result<u64, AttErrorCode> {
+    let data: &[u8; CURRENT_TIME_LEN] = data
+        .try_into()
+        .map_err(|_| AttErrorCode::INVALID_ATTRIBUTE_VALUE_LENGTH)?;
+    let year = u16::from_le_bytes([data[0], data[1]]);
+    let unix_ms = unix_ms_from_utc(year, data[2], data[3], data[4], data[5], data[6])
+        .ok_or(AttErrorCode::VALUE_NOT_ALLOWED)?;
+
+    // data[7] is the day of the week, data[8] counts 1/256ths of a second and
+    // data[9] is the adjust reason
+    Ok(unix_ms + data[8] as u64 * 1000 / 256)
+}
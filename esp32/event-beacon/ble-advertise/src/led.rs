@@ -50,8 +50,11 @@ pub async fn off(channel: &mut Channel<'static, Async, Tx>) -> Result<(), esp_ha
 //     channel.transmit(&pulses).await
 // }
 
-pub async fn red_led(channel: &mut Channel<'static, Async, Tx>) -> Result<(), esp_hal::rmt::Error> {
-    let pulses = rgb_to_pulses((10, 0, 0)).map_err(|_e| esp_hal::rmt::Error::InvalidDataLength)?;
+pub async fn show(
+    channel: &mut Channel<'static, Async, Tx>,
+    colour: (u8, u8, u8),
+) -> Result<(), esp_hal::rmt::Error> {
+    let pulses = rgb_to_pulses(colour).map_err(|_e| esp_hal::rmt::Error::InvalidDataLength)?;
     channel.transmit(&pulses).await
 }
 
@@ -1,26 +1,40 @@
 #![no_std]
 #![no_main]
 
+mod bonding;
 mod common;
+mod config;
+mod flash;
 mod gatt;
 mod led;
+mod snapshot;
 
-use crate::gatt::advertise_and_handle_connection;
-use crate::led::{create_channel, off, red_led};
+use crate::bonding::Bonding;
+use crate::config::Settings;
+use crate::flash::SharedFlash;
+use crate::gatt::{MAX_CONNECTIONS, Notifications, advertise_and_handle_connections};
+use crate::led::{create_channel, off, show};
+use crate::snapshot::{SNAPSHOT_LEN, create_store, persist_events, restore_events};
+use core::cell::RefCell;
 use core::future::pending;
 use defmt::Debug2Format;
 use embassy_executor::Spawner;
-use embassy_futures::select::select4;
-use embassy_time::Timer;
+use embassy_futures::join::{join, join3};
+use embassy_futures::select::{Either, select, select4};
+use embassy_time::{Instant, Timer};
 use esp_hal::Async;
 use esp_hal::clock::CpuClock;
 use esp_hal::gpio::{Input, InputConfig, Level};
 use esp_hal::rmt::{Channel, Tx};
+use esp_hal::rng::Trng;
 use esp_hal::rtc_cntl::Rtc;
 use esp_hal::time::Duration;
 use esp_hal::timer::timg::TimerGroup;
 use esp_radio::ble::controller::BleConnector;
-use event_storage::storage::Events;
+use esp_storage::FlashStorage;
+use event_storage::alerts::{Condition, Rule};
+use event_storage::occupancy::Classifier;
+use event_storage::storage::{DEFAULT_CAPACITY, Events, RecordType};
 use static_cell::StaticCell;
 use trouble_host::HostResources;
 use trouble_host::prelude::*;
@@ -63,62 +77,196 @@ async fn main(_spawner: Spawner) -> ! {
     let controller: ExternalController<_, 1> =
         trouble_host::prelude::ExternalController::new(connector);
 
-    let mut resources: HostResources<DefaultPacketPool, 1, 2> = HostResources::new();
-    let stack = trouble_host::new(controller, &mut resources);
+    let mut resources: HostResources<DefaultPacketPool, MAX_CONNECTIONS, { 2 * MAX_CONNECTIONS }> =
+        HostResources::new();
+    // Seeds the security manager's keys for LE Secure Connections pairing
+    let mut trng = Trng::new(peripherals.RNG, peripherals.ADC1);
+    let stack = trouble_host::new(controller, &mut resources).set_random_generator_seed(&mut trng);
+    // The pairing button answers numeric comparison, so a central can only
+    // pair with authentication while someone at the beacon confirms it
+    stack.set_io_capabilities(IoCapabilities::DisplayYesNo);
     let Host {
         mut peripheral,
         mut runner,
         ..
     } = stack.build();
 
-    let events = Events::default();
-    let input = Input::new(
+    static FLASH: StaticCell<RefCell<FlashStorage<'static>>> = StaticCell::new();
+    let flash = FLASH.init(RefCell::new(FlashStorage::new(peripherals.FLASH)));
+
+    let settings = Settings::load(config::create_store(SharedFlash(flash)));
+    let bonding = Bonding::load(bonding::create_store(SharedFlash(flash)), &stack);
+    let events = Events::new(settings.get().breakpoints());
+
+    static SNAPSHOT_BUFFER: StaticCell<[u8; SNAPSHOT_LEN]> = StaticCell::new();
+    let snapshot_buffer = SNAPSHOT_BUFFER.init([0; SNAPSHOT_LEN]);
+    let mut store = create_store(SharedFlash(flash));
+    restore_events(&events, &mut store, snapshot_buffer).await;
+
+    // Lights the LED while the input has been High within the last second
+    let activity_rule = events
+        .add_rule(Rule {
+            channel: 0,
+            condition: Condition::CountAtLeast {
+                bucket: 0,
+                trip: 1,
+                clear: 0,
+            },
+            hold_off_ms: 0,
+        })
+        .await
+        .map_err(|e| defmt::error!("could not add activity rule error={:?}", Debug2Format(&e)))
+        .ok();
+    if let Err(e) = events
+        .add_rule(Rule {
+            channel: 0,
+            condition: Condition::NoHighFor { trip_ms: 600_000 },
+            hold_off_ms: 0,
+        })
+        .await
+    {
+        defmt::error!("could not add silence rule error={:?}", Debug2Format(&e));
+    }
+
+    // Advertised as a single state byte, judged on the last 30 seconds
+    if let Err(e) = events
+        .set_classifier(
+            0,
+            Classifier {
+                bucket: 2,
+                active_at: 10,
+                busy_at: 60,
+                min_dwell_ms: [5_000, 30_000, 30_000],
+            },
+        )
+        .await
+    {
+        defmt::error!("could not set classifier error={:?}", Debug2Format(&e));
+    }
+
+    let inputs = [Input::new(
         peripherals.GPIO3,
         InputConfig::default().with_pull(esp_hal::gpio::Pull::None),
+    )];
+    // The BOOT button, pulled up and pressed to ground
+    let mut pairing_button = Input::new(
+        peripherals.GPIO9,
+        InputConfig::default().with_pull(esp_hal::gpio::Pull::Up),
     );
 
     let _ = select4(
         runner.run(),
-        collect_events(&input, &events, &mut led_channel),
-        advertise_and_handle_connection(&events, &mut peripheral),
-        async {
-            loop {
-                Timer::after_secs(3).await;
-                watchdog.feed();
-            }
-            // let mut sleep_config = RtcSleepConfig::default();
-            // Timer::after_secs(5).await;
-            // esp_println::dbg!("going to sleep for five seconds");
-            // let wakeup_source = TimerWakeupSource::new(Duration::from_millis(5000));
-            // rtc.sleep(&sleep_config, &[&wakeup_source]);
-        },
+        join(
+            collect_events(&inputs, &events, &settings),
+            show_alerts(&events, &settings, activity_rule, &mut led_channel),
+        ),
+        advertise_and_handle_connections(
+            &events,
+            &settings,
+            &bonding,
+            Notifications::OnChange,
+            &mut peripheral,
+        ),
+        join3(
+            persist_events(&events, &mut store, snapshot_buffer),
+            watch_pairing_button(&mut pairing_button, &bonding),
+            async {
+                loop {
+                    Timer::after_secs(3).await;
+                    watchdog.feed();
+                }
+                // let mut sleep_config = RtcSleepConfig::default();
+                // Timer::after_secs(5).await;
+                // esp_println::dbg!("going to sleep for five seconds");
+                // let wakeup_source = TimerWakeupSource::new(Duration::from_millis(5000));
+                // rtc.sleep(&sleep_config, &[&wakeup_source]);
+            },
+        ),
     )
     .await;
 
     pending().await
 }
 
-async fn collect_events(
-    input: &Input<'static>,
-    events: &Events,
+/// Passes presses of the pairing button on to `bonding`.
+async fn watch_pairing_button(button: &mut Input<'static>, bonding: &Bonding<'_, '_>) -> ! {
+    loop {
+        button.wait_for_falling_edge().await;
+        bonding.button_pressed();
+    }
+}
+
+/// Samples every input into the matching `Events` channel, at the period the
+/// settings hold at the time.
+async fn collect_events<const CHANNELS: usize>(
+    inputs: &[Input<'static>; CHANNELS],
+    events: &Events<8, DEFAULT_CAPACITY, CHANNELS>,
+    settings: &Settings,
+) {
+    // Sampling on whole-millisecond deadlines, and stamping each sample with
+    // its deadline rather than the time the task got round to it, keeps
+    // samples on the history's cadence, so they pack into as few blocks as
+    // possible.
+    let mut deadline = Instant::from_millis(Instant::now().as_millis());
+
+    loop {
+        for (channel, input) in inputs.iter().enumerate() {
+            let record_type = match input.level() {
+                Level::High => RecordType::High,
+                Level::Low => RecordType::Low,
+            };
+            let _ = events
+                .record_channel_at(channel, record_type, deadline)
+                .await;
+        }
+
+        let sample_period = settings.get().sample_period();
+        deadline += sample_period;
+        let now = Instant::now();
+        if deadline < now {
+            // Skip the samples missed while the executor was busy rather than
+            // recording a burst of them late.
+            deadline = Instant::from_millis(now.as_millis()) + sample_period;
+        }
+        Timer::at(deadline).await;
+    }
+}
+
+/// Lights the LED in the configured colour while `rule` is tripped. The LED
+/// stays dark without a rule.
+async fn show_alerts<const CHANNELS: usize>(
+    events: &Events<8, DEFAULT_CAPACITY, CHANNELS>,
+    settings: &Settings,
+    rule: Option<usize>,
     led_channel: &mut Channel<'static, Async, Tx>,
 ) {
+    let Some(rule) = rule else {
+        return;
+    };
+    let Ok(mut alerts) = events.alert_subscriber() else {
+        defmt::error!("no alert subscriber left for the LED");
+        return;
+    };
+    let Some(mut changes) = settings.receiver() else {
+        defmt::error!("no settings receiver left for the LED");
+        return;
+    };
+    let mut lit = false;
+
     loop {
-        match input.level() {
-            Level::High => {
-                events
-                    .record(event_storage::storage::RecordType::High)
-                    .await;
-
-                let _result = red_led(led_channel).await;
-                // defmt::error!("{}", Debug2Format(&result));
-            }
-            Level::Low => {
-                events.record(event_storage::storage::RecordType::Low).await;
-                let _ = off(led_channel).await;
-            }
+        match select(alerts.next_message_pure(), changes.changed()).await {
+            Either::First(alert) if alert.rule == rule => lit = alert.active,
+            Either::First(_) => continue,
+            // A new colour shows straight away if the LED is lit
+            Either::Second(_) if lit => {}
+            Either::Second(_) => continue,
         }
 
-        Timer::after_millis(100).await;
+        if lit {
+            let _result = show(led_channel, settings.get().led_colour()).await;
+            // defmt::error!("{}", Debug2Format(&result));
+        } else {
+            let _ = off(led_channel).await;
+        }
     }
 }
@@ -0,0 +1,72 @@
+use crate::flash::SharedFlash;
+use defmt::Debug2Format;
+use embassy_time::Timer;
+use event_storage::persist::FlashStore;
+use event_storage::storage::{DEFAULT_CAPACITY, Events};
+
+/// Snapshots live in the `history` partition that `partitions.csv` adds
+/// after the app, in slots of two sectors.
+const SNAPSHOT_START: u32 = 0x310000;
+const SNAPSHOT_SLOT_SIZE: u32 = 0x2000;
+const SNAPSHOT_SLOTS: u32 = 32;
+
+/// How often a snapshot may be saved. Saves rotate through every slot, so
+/// even saving on every interval erases each sector about 3,300 times a
+/// year, well within its rated 100,000 cycles.
+const SAVE_INTERVAL_SECS: u64 = 300;
+
+pub const SNAPSHOT_LEN: usize = Events::<8, DEFAULT_CAPACITY>::SNAPSHOT_LEN;
+
+pub type SnapshotStore = FlashStore<SharedFlash<'static>>;
+
+pub fn create_store(flash: SharedFlash<'static>) -> SnapshotStore {
+    FlashStore::new(flash, SNAPSHOT_START, SNAPSHOT_SLOT_SIZE, SNAPSHOT_SLOTS).unwrap()
+}
+
+/// Loads the newest snapshot, if there is one, into `events`.
+pub async fn restore_events(
+    events: &Events,
+    store: &mut SnapshotStore,
+    buffer: &mut [u8; SNAPSHOT_LEN],
+) {
+    match store.load(buffer) {
+        Ok(Some(len)) => match events.restore(&buffer[..len]).await {
+            Ok(()) => defmt::info!("restored snapshot length={}", len),
+            Err(e) => defmt::warn!("could not restore snapshot error={:?}", Debug2Format(&e)),
+        },
+        Ok(None) => defmt::info!("no snapshot to restore"),
+        Err(e) => defmt::warn!("could not load snapshot error={:?}", Debug2Format(&e)),
+    }
+}
+
+/// Saves a snapshot of `events` every few minutes, so a reset loses at most
+/// that much history. Saves are skipped while no new block has started: the
+/// lost samples would only have extended the newest block, so an input on a
+/// steady cadence barely wears the flash.
+pub async fn persist_events(
+    events: &Events,
+    store: &mut SnapshotStore,
+    buffer: &mut [u8; SNAPSHOT_LEN],
+) -> ! {
+    let mut saved = events.history().await.last_sequence();
+    loop {
+        Timer::after_secs(SAVE_INTERVAL_SECS).await;
+
+        let newest = events.history().await.last_sequence();
+        if newest == saved {
+            continue;
+        }
+
+        let len = match events.snapshot(buffer).await {
+            Ok(len) => len,
+            Err(e) => {
+                defmt::warn!("could not take snapshot error={:?}", Debug2Format(&e));
+                continue;
+            }
+        };
+        match store.save(&buffer[..len]) {
+            Ok(()) => saved = newest,
+            Err(e) => defmt::warn!("could not save snapshot error={:?}", Debug2Format(&e)),
+        }
+    }
+}
@@ -0,0 +1,236 @@
+//! Threshold rules evaluated as samples are recorded.
+
+use embassy_sync::{blocking_mutex::raw::NoopRawMutex, pubsub::Subscriber};
+
+use crate::windows::Windows;
+
+/// Number of rules a single `Events` can hold.
+pub const MAX_RULES: usize = 8;
+/// Alerts buffered for each subscriber before the oldest are dropped.
+pub const ALERT_QUEUE_LEN: usize = 8;
+/// Number of tasks that can wait on alerts at the same time.
+pub const ALERT_SUBSCRIBERS: usize = 4;
+
+pub type AlertSubscriber<'a, M = NoopRawMutex> =
+    Subscriber<'a, M, Alert, ALERT_QUEUE_LEN, ALERT_SUBSCRIBERS, 0>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Condition {
+    /// Trips once the High count in window `bucket` reaches `trip` and clears
+    /// once it falls to `clear` or below. Setting `clear` below `trip` keeps
+    /// a count hovering around the threshold from flapping.
+    CountAtLeast {
+        bucket: usize,
+        trip: u16,
+        clear: u16,
+    },
+    /// Trips once no High sample has been recorded for `trip_ms` milliseconds
+    /// and clears on the next High sample.
+    NoHighFor { trip_ms: u64 },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rule {
+    pub channel: usize,
+    pub condition: Condition,
+    /// How long, in milliseconds, the condition must hold before the alert
+    /// trips, or stop holding before it clears.
+    pub hold_off_ms: u64,
+}
+
+/// A rule tripping or clearing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Alert {
+    /// Index of the rule, as returned when it was added.
+    pub rule: usize,
+    pub channel: usize,
+    /// Whether the rule tripped (`true`) or cleared (`false`).
+    pub active: bool,
+    /// Timestamp of the sample that changed the alert, in milliseconds.
+    pub timestamp: u64,
+}
+
+/// A rule and the state of its alert.
+pub(crate) struct Tracker {
+    pub(crate) rule: Rule,
+    active: bool,
+    changing_since: Option<u64>,
+}
+
+impl Tracker {
+    pub(crate) fn new(rule: Rule) -> Self {
+        Self {
+            rule,
+            active: false,
+            changing_since: None,
+        }
+    }
+
+    /// Re-evaluates the rule after a sample at `now` and returns the new state
+    /// if the alert changed. `quiet_since` is when the channel last saw a High
+    /// sample, or its first sample if it has never seen one.
+    pub(crate) fn update<const WINDOWS: usize>(
+        &mut self,
+        windows: &Windows<WINDOWS>,
+        quiet_since: Option<u64>,
+        now: u64,
+    ) -> Option<bool> {
+        let holds = match self.rule.condition {
+            Condition::CountAtLeast {
+                bucket,
+                trip,
+                clear,
+            } => {
+                let count = windows.counts().get(bucket).copied().unwrap_or(0);
+                if self.active {
+                    count > clear
+                } else {
+                    count >= trip
+                }
+            }
+            Condition::NoHighFor { trip_ms } => {
+                quiet_since.is_some_and(|since| now.saturating_sub(since) >= trip_ms)
+            }
+        };
+
+        if holds == self.active {
+            self.changing_since = None;
+            return None;
+        }
+
+        let since = *self.changing_since.get_or_insert(now);
+        if now.saturating_sub(since) < self.rule.hold_off_ms {
+            return None;
+        }
+
+        self.active = holds;
+        self.changing_since = None;
+        Some(self.active)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::History;
+
+    struct Harness {
+        history: History<16>,
+        windows: Windows<1>,
+        quiet_since: Option<u64>,
+        tracker: Tracker,
+    }
+
+    impl Harness {
+        fn new(condition: Condition, hold_off_ms: u64) -> Self {
+            Self {
+                history: History::new(),
+                windows: Windows::new([1000]),
+                quiet_since: None,
+                tracker: Tracker::new(Rule {
+                    channel: 0,
+                    condition,
+                    hold_off_ms,
+                }),
+            }
+        }
+
+        fn record(&mut self, timestamp: u64, high: bool) -> Option<bool> {
+            let timestamp = self.history.align(timestamp, high);
+            let evicted = self.history.push(timestamp, high);
+            self.windows.record(&self.history, evicted, timestamp, high);
+            if high || self.quiet_since.is_none() {
+                self.quiet_since = Some(timestamp);
+            }
+            self.tracker
+                .update(&self.windows, self.quiet_since, timestamp)
+        }
+    }
+
+    #[test]
+    fn test_count_rule_uses_hysteresis() {
+        let mut harness = Harness::new(
+            Condition::CountAtLeast {
+                bucket: 0,
+                trip: 3,
+                clear: 1,
+            },
+            0,
+        );
+
+        let mut changes = heapless::Vec::<(u64, bool), 8>::new();
+        for i in 0..15 {
+            let timestamp = i * 100;
+            let high = !(3..12).contains(&i);
+            if let Some(active) = harness.record(timestamp, high) {
+                changes.push((timestamp, active)).unwrap();
+            }
+        }
+
+        // Trips on the third High, holds while the count dips to 2 at 1000ms
+        // and clears only once it falls to 1
+        assert_eq!(
+            changes.as_slice(),
+            &[(200, true), (1100, false), (1400, true)]
+        );
+    }
+
+    #[test]
+    fn test_hold_off_ignores_brief_changes() {
+        let mut harness = Harness::new(
+            Condition::CountAtLeast {
+                bucket: 0,
+                trip: 1,
+                clear: 0,
+            },
+            1500,
+        );
+
+        // A lone High sample leaves the window before the hold-off has passed
+        assert_eq!(harness.record(0, true), None);
+        for i in 1..20 {
+            assert_eq!(harness.record(i * 100, false), None);
+        }
+
+        // A sustained High trips once the hold-off has passed
+        for i in 20..35 {
+            assert_eq!(harness.record(i * 100, true), None);
+        }
+        assert_eq!(harness.record(3500, true), Some(true));
+    }
+
+    #[test]
+    fn test_silence_rule_trips_and_clears() {
+        let mut harness = Harness::new(Condition::NoHighFor { trip_ms: 500 }, 0);
+
+        assert_eq!(harness.record(0, true), None);
+        for i in 1..5 {
+            assert_eq!(harness.record(i * 100, false), None);
+        }
+        assert_eq!(harness.record(500, false), Some(true));
+        assert_eq!(harness.record(600, false), None);
+        assert_eq!(harness.record(700, true), Some(false));
+    }
+
+    #[test]
+    fn test_silence_rule_outlasts_history() {
+        let mut harness = Harness::new(Condition::NoHighFor { trip_ms: 10_000 }, 0);
+
+        // Samples stamped with the same time cannot share a block, so a burst
+        // of Lows pushes the High out of history
+        assert_eq!(harness.record(0, true), None);
+        for _ in 0..20 {
+            assert_eq!(harness.record(1, false), None);
+        }
+        assert!(harness.history.runs().all(|run| !run.high));
+
+        let mut tripped = None;
+        for i in 1..200 {
+            if harness.record(i * 100, false) == Some(true) {
+                tripped = Some(i * 100);
+                break;
+            }
+        }
+        assert_eq!(tripped, Some(10_000));
+    }
+}
@@ -0,0 +1,125 @@
+//! Replays a recorded input trace and prints the report timeline as CSV.
+//!
+//! ```text
+//! cargo run --features replay --target x86_64-unknown-linux-gnu --bin replay -- \
+//!     trace.csv [--breakpoints 1000,5000,...] [--every 60000]
+//! ```
+//!
+//! The workspace builds for the ESP32 by default, so pass the host target.
+//! See [`event_storage::replay`] for the trace format. `--breakpoints` takes
+//! eight window lengths in milliseconds and `--every` reports every channel on
+//! a fixed interval instead of after each sample.
+
+use std::{env, fs, process::ExitCode};
+
+use event_storage::{
+    Events,
+    replay::{self, Frame},
+    storage::{DEFAULT_BREAKPOINTS, DEFAULT_CAPACITY},
+};
+
+const CHANNELS: usize = 8;
+const CHANNEL_NAMES: [&str; CHANNELS] = ["0", "1", "2", "3", "4", "5", "6", "7"];
+
+const USAGE: &str = "usage: replay <trace.csv> [--breakpoints ms,ms,...] [--every ms]";
+
+struct Options {
+    path: String,
+    breakpoints: [u64; 8],
+    every_ms: Option<u64>,
+}
+
+fn parse_options(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut path = None;
+    let mut breakpoints = DEFAULT_BREAKPOINTS;
+    let mut every_ms = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--breakpoints" => {
+                let value = args.next().ok_or("--breakpoints needs a value")?;
+                let parsed = value
+                    .split(',')
+                    .map(|breakpoint| breakpoint.trim().parse::<u64>())
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|e| format!("invalid breakpoint: {e}"))?;
+                breakpoints = parsed.try_into().map_err(|parsed: Vec<u64>| {
+                    format!("expected 8 breakpoints, got {}", parsed.len())
+                })?;
+            }
+            "--every" => {
+                let value = args.next().ok_or("--every needs a value")?;
+                let value = value
+                    .parse()
+                    .map_err(|e| format!("invalid interval: {e}"))?;
+                every_ms = Some(value);
+            }
+            _ if path.is_none() && !arg.starts_with("--") => path = Some(arg),
+            _ => return Err(format!("unexpected argument: {arg}")),
+        }
+    }
+
+    Ok(Options {
+        path: path.ok_or("missing trace path")?,
+        breakpoints,
+        every_ms,
+    })
+}
+
+fn main() -> ExitCode {
+    let options = match parse_options(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{e}\n{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let trace = match fs::read_to_string(&options.path) {
+        Ok(trace) => trace,
+        Err(e) => {
+            eprintln!("could not read {}: {e}", options.path);
+            return ExitCode::FAILURE;
+        }
+    };
+    let samples = match replay::parse_trace(&trace) {
+        Ok(samples) => samples,
+        Err(e) => {
+            eprintln!("{}: {e}", options.path);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    // Only print the channels the trace uses
+    let channels = samples
+        .iter()
+        .map(|sample| sample.channel + 1)
+        .max()
+        .unwrap_or(0);
+    let events: Events<8, DEFAULT_CAPACITY, CHANNELS> =
+        Events::with_channels(CHANNEL_NAMES, options.breakpoints);
+
+    print!("timestamp_ms,channel");
+    for breakpoint in options.breakpoints {
+        print!(",{breakpoint}ms");
+    }
+    println!();
+
+    let result = replay::replay(&events, &samples, options.every_ms, |frame: Frame<8>| {
+        if frame.channel >= channels {
+            return;
+        }
+        print!("{},{}", frame.timestamp_ms, frame.channel);
+        for count in frame.counts {
+            print!(",{count}");
+        }
+        println!();
+    });
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}: {e}", options.path);
+            ExitCode::FAILURE
+        }
+    }
+}
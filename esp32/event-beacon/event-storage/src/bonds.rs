@@ -0,0 +1,293 @@
+//! Bonds with paired centrals, as they are kept in flash.
+//!
+//! A bond holds the keys a central and the beacon agreed on when pairing, so
+//! the link can be encrypted again on reconnection without pairing anew. The
+//! table is stored as:
+//!
+//! | offset | size     | field                                  |
+//! |--------|----------|----------------------------------------|
+//! | 0      | 1        | version, [`BONDS_VERSION`]             |
+//! | 1      | 1        | bond count `n`                         |
+//! | 2      | `39 * n` | bonds, least recently paired first     |
+//!
+//! and each bond as:
+//!
+//! | offset | size | field                                       |
+//! |--------|------|---------------------------------------------|
+//! | 0      | 1    | flags                                       |
+//! | 1      | 6    | identity address, least significant first   |
+//! | 7      | 16   | long-term key                               |
+//! | 23     | 16   | identity resolving key, zeros without one   |
+//!
+//! Keys are kept in the byte order the security manager hands them over in.
+
+use crate::Error;
+
+pub const BONDS_VERSION: u8 = 1;
+pub const BOND_LEN: usize = 39;
+const HEADER_LEN: usize = 2;
+
+/// The central shared an identity resolving key.
+pub const FLAG_IRK: u8 = 0b0000_0001;
+/// Pairing protected against man-in-the-middle attacks.
+pub const FLAG_AUTHENTICATED: u8 = 0b0000_0010;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Bond {
+    pub address: [u8; 6],
+    pub ltk: [u8; 16],
+    pub irk: Option<[u8; 16]>,
+    /// The keys were agreed on over an authenticated pairing, so links they
+    /// encrypt count as authenticated too.
+    pub authenticated: bool,
+}
+
+impl Bond {
+    pub fn encode(&self, bytes: &mut [u8]) {
+        let mut flags = 0;
+        if self.irk.is_some() {
+            flags |= FLAG_IRK;
+        }
+        if self.authenticated {
+            flags |= FLAG_AUTHENTICATED;
+        }
+        bytes[0] = flags;
+        bytes[1..7].copy_from_slice(&self.address);
+        bytes[7..23].copy_from_slice(&self.ltk);
+        bytes[23..39].copy_from_slice(&self.irk.unwrap_or_default());
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, Error> {
+        let bytes: &[u8; BOND_LEN] = bytes.try_into().map_err(|_| Error::InvalidBonds)?;
+        let flags = bytes[0];
+        if flags & !(FLAG_IRK | FLAG_AUTHENTICATED) != 0 {
+            return Err(Error::InvalidBonds);
+        }
+
+        let mut address = [0; 6];
+        address.copy_from_slice(&bytes[1..7]);
+        let mut ltk = [0; 16];
+        ltk.copy_from_slice(&bytes[7..23]);
+        let mut irk = [0; 16];
+        irk.copy_from_slice(&bytes[23..39]);
+
+        Ok(Self {
+            address,
+            ltk,
+            irk: (flags & FLAG_IRK != 0).then_some(irk),
+            authenticated: flags & FLAG_AUTHENTICATED != 0,
+        })
+    }
+}
+
+/// Up to `N` bonds, one per identity address. Pairing with another central
+/// once full forgets the one paired longest ago.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Bonds<const N: usize> {
+    bonds: heapless::Vec<Bond, N>,
+}
+
+impl<const N: usize> Bonds<N> {
+    /// Bytes needed to encode a full table.
+    pub const ENCODED_LEN: usize = HEADER_LEN + N * BOND_LEN;
+
+    pub const fn new() -> Self {
+        Self {
+            bonds: heapless::Vec::new(),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Bond> {
+        self.bonds.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.bonds.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bonds.is_empty()
+    }
+
+    pub fn get(&self, address: &[u8; 6]) -> Option<&Bond> {
+        self.bonds.iter().find(|bond| &bond.address == address)
+    }
+
+    /// Adds `bond`, replacing any earlier bond with the same address, and
+    /// returns the bond that was forgotten to make room for it.
+    pub fn insert(&mut self, bond: Bond) -> Option<Bond> {
+        self.remove(&bond.address);
+        let evicted = if self.bonds.is_full() && N > 0 {
+            Some(self.bonds.remove(0))
+        } else {
+            None
+        };
+        let _ = self.bonds.push(bond);
+        evicted
+    }
+
+    pub fn remove(&mut self, address: &[u8; 6]) -> Option<Bond> {
+        let index = self
+            .bonds
+            .iter()
+            .position(|bond| &bond.address == address)?;
+        Some(self.bonds.remove(index))
+    }
+
+    /// Writes the table into `bytes` and returns the number of bytes
+    /// written.
+    pub fn encode(&self, bytes: &mut [u8]) -> Result<usize, Error> {
+        let len = HEADER_LEN + self.bonds.len() * BOND_LEN;
+        if bytes.len() < len {
+            return Err(Error::BufferLength {
+                expected: len,
+                actual: bytes.len(),
+            });
+        }
+
+        bytes[0] = BONDS_VERSION;
+        bytes[1] = self.bonds.len() as u8;
+        for (bond, chunk) in self
+            .bonds
+            .iter()
+            .zip(bytes[HEADER_LEN..len].chunks_exact_mut(BOND_LEN))
+        {
+            bond.encode(chunk);
+        }
+        Ok(len)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.len() < HEADER_LEN || bytes[0] != BONDS_VERSION {
+            return Err(Error::InvalidBonds);
+        }
+        let count = bytes[1] as usize;
+        if count > N || bytes.len() != HEADER_LEN + count * BOND_LEN {
+            return Err(Error::InvalidBonds);
+        }
+
+        let mut bonds = Self::new();
+        for chunk in bytes[HEADER_LEN..].chunks_exact(BOND_LEN) {
+            let bond = Bond::decode(chunk)?;
+            if bonds.get(&bond.address).is_some() {
+                return Err(Error::InvalidBonds);
+            }
+            let _ = bonds.bonds.push(bond);
+        }
+        Ok(bonds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{mock::MockFlash, persist::FlashStore};
+
+    fn bond(id: u8, irk: bool) -> Bond {
+        Bond {
+            address: [id, 0x11, 0x22, 0x33, 0x44, 0xc5],
+            ltk: [id ^ 0x5a; 16],
+            irk: irk.then_some([id ^ 0xa5; 16]),
+            authenticated: id.is_multiple_of(2),
+        }
+    }
+
+    #[test]
+    fn test_bonds_round_trip() {
+        let mut bonds: Bonds<4> = Bonds::new();
+        bonds.insert(bond(1, true));
+        bonds.insert(bond(2, false));
+
+        let mut bytes = [0; Bonds::<4>::ENCODED_LEN];
+        let len = bonds.encode(&mut bytes).unwrap();
+        assert_eq!(len, HEADER_LEN + 2 * BOND_LEN);
+        assert_eq!(&bytes[..2], &[BONDS_VERSION, 2]);
+        assert_eq!(bytes[HEADER_LEN], FLAG_IRK);
+        assert_eq!(bytes[HEADER_LEN + BOND_LEN], FLAG_AUTHENTICATED);
+        assert_eq!(Bonds::<4>::decode(&bytes[..len]), Ok(bonds));
+
+        assert_eq!(
+            Bonds::<4>::new().encode(&mut bytes[..1]),
+            Err(Error::BufferLength {
+                expected: 2,
+                actual: 1
+            })
+        );
+    }
+
+    #[test]
+    fn test_insert_replaces_and_evicts() {
+        let mut bonds: Bonds<2> = Bonds::new();
+        assert_eq!(bonds.insert(bond(1, false)), None);
+        assert_eq!(bonds.insert(bond(2, false)), None);
+
+        // Pairing again moves a central to the back with its new keys
+        let mut repaired = bond(1, true);
+        repaired.ltk = [0xff; 16];
+        assert_eq!(bonds.insert(repaired), None);
+        assert_eq!(bonds.get(&bond(1, false).address), Some(&repaired));
+
+        assert_eq!(bonds.insert(bond(3, false)), Some(bond(2, false)));
+        let addresses: Vec<u8> = bonds.iter().map(|bond| bond.address[0]).collect();
+        assert_eq!(addresses, [1, 3]);
+
+        assert_eq!(bonds.remove(&repaired.address), Some(repaired));
+        assert_eq!(bonds.remove(&repaired.address), None);
+        assert_eq!(bonds.len(), 1);
+    }
+
+    #[test]
+    fn test_invalid_bonds_are_rejected() {
+        let mut bonds: Bonds<2> = Bonds::new();
+        bonds.insert(bond(1, true));
+        bonds.insert(bond(2, true));
+        let mut bytes = [0; Bonds::<2>::ENCODED_LEN];
+        let len = bonds.encode(&mut bytes).unwrap();
+
+        for invalid in [
+            &bytes[..1],
+            &bytes[..len - 1],
+            // Unknown version
+            &[2, 0][..],
+        ] {
+            assert_eq!(Bonds::<2>::decode(invalid), Err(Error::InvalidBonds));
+        }
+
+        // More bonds than the table holds
+        assert_eq!(Bonds::<1>::decode(&bytes[..len]), Err(Error::InvalidBonds));
+
+        // Unknown flags
+        let mut flagged = bytes;
+        flagged[HEADER_LEN] |= 0b1000_0000;
+        assert_eq!(
+            Bonds::<2>::decode(&flagged[..len]),
+            Err(Error::InvalidBonds)
+        );
+
+        // The same address twice
+        let mut duplicate = bytes;
+        duplicate[HEADER_LEN + BOND_LEN + 1] = 1;
+        assert_eq!(
+            Bonds::<2>::decode(&duplicate[..len]),
+            Err(Error::InvalidBonds)
+        );
+    }
+
+    #[test]
+    fn test_bonds_survive_flash_store() {
+        let mut flash: MockFlash<{ 2 * 4096 }> = MockFlash::new();
+        let mut bonds: Bonds<4> = Bonds::new();
+        bonds.insert(bond(1, true));
+        bonds.insert(bond(2, false));
+
+        let mut bytes = [0; Bonds::<4>::ENCODED_LEN];
+        let len = bonds.encode(&mut bytes).unwrap();
+        let mut store = FlashStore::new(&mut flash, 0, 4096, 2).unwrap();
+        store.save(&bytes[..len]).unwrap();
+
+        let mut store = FlashStore::new(&mut flash, 0, 4096, 2).unwrap();
+        let mut loaded = [0; Bonds::<4>::ENCODED_LEN];
+        let len = store.load(&mut loaded).unwrap().unwrap();
+        assert_eq!(Bonds::<4>::decode(&loaded[..len]), Ok(bonds));
+    }
+}
@@ -0,0 +1,121 @@
+//! Mapping from [`crate::Events`] timestamps to wall-clock time.
+//!
+//! Samples are always stored against the monotonic clock, so windows and
+//! history never see the wall clock jump. A time sync only records the offset
+//! between the two, and a later correction replaces it, shifting every
+//! exported timestamp, old ones included, by the same amount.
+
+/// Offset between stored timestamps and Unix time, once it is known.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Clock {
+    offset_ms: Option<i64>,
+}
+
+impl Clock {
+    pub const fn new() -> Self {
+        Self { offset_ms: None }
+    }
+
+    pub fn is_synced(&self) -> bool {
+        self.offset_ms.is_some()
+    }
+
+    /// Pairs the stored `timestamp` with `unix_ms`, milliseconds since the
+    /// Unix epoch, and returns how far that moved the clock if it had already
+    /// been synced.
+    pub fn sync(&mut self, timestamp: u64, unix_ms: u64) -> Option<i64> {
+        let offset_ms = unix_ms as i64 - timestamp as i64;
+        let correction = self.offset_ms.map(|previous| offset_ms - previous);
+        self.offset_ms = Some(offset_ms);
+        correction
+    }
+
+    /// Converts a stored timestamp to milliseconds since the Unix epoch.
+    pub fn to_unix_ms(&self, timestamp: u64) -> Option<u64> {
+        let unix_ms = timestamp as i64 + self.offset_ms?;
+        Some(unix_ms.max(0) as u64)
+    }
+
+    /// Keeps the wall-clock time of future samples when stored timestamps
+    /// move by `delta_ms`.
+    pub(crate) fn shift(&mut self, delta_ms: i64) {
+        if let Some(offset_ms) = self.offset_ms.as_mut() {
+            *offset_ms -= delta_ms;
+        }
+    }
+}
+
+/// Converts a UTC calendar date and time to milliseconds since the Unix
+/// epoch, or `None` if any field is out of range.
+pub fn unix_ms_from_utc(
+    year: u16,
+    month: u8,
+    day: u8,
+    hour: u8,
+    minute: u8,
+    second: u8,
+) -> Option<u64> {
+    let leap = year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400));
+    let days_in_month = match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if leap => 29,
+        2 => 28,
+        _ => return None,
+    };
+    if year < 1970 || day == 0 || day > days_in_month || hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+
+    // Days since 0000-03-01, counting March as the first month so the leap
+    // day falls at the end of the year
+    let (year, month) = if month > 2 {
+        (year as u64, month as u64 - 3)
+    } else {
+        (year as u64 - 1, month as u64 + 9)
+    };
+    let days =
+        year * 365 + year / 4 - year / 100 + year / 400 + (153 * month + 2) / 5 + day as u64 - 1;
+    // 1970-01-01 is day 719468 counted that way
+    let days = days - 719_468;
+
+    let seconds = days * 86_400 + hour as u64 * 3600 + minute as u64 * 60 + second as u64;
+    Some(seconds * 1000)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_calendar_conversion() {
+        assert_eq!(unix_ms_from_utc(1970, 1, 1, 0, 0, 0), Some(0));
+        assert_eq!(
+            unix_ms_from_utc(2000, 2, 29, 12, 0, 0),
+            Some(951_825_600_000)
+        );
+        assert_eq!(
+            unix_ms_from_utc(2024, 12, 31, 23, 59, 59),
+            Some(1_735_689_599_000)
+        );
+        assert_eq!(unix_ms_from_utc(2023, 2, 29, 0, 0, 0), None);
+        assert_eq!(unix_ms_from_utc(2024, 13, 1, 0, 0, 0), None);
+        assert_eq!(unix_ms_from_utc(1969, 12, 31, 0, 0, 0), None);
+    }
+
+    #[test]
+    fn test_corrections_move_every_timestamp() {
+        let mut clock = Clock::new();
+        assert_eq!(clock.to_unix_ms(1000), None);
+
+        assert_eq!(clock.sync(5000, 1_700_000_000_000), None);
+        assert_eq!(clock.to_unix_ms(1000), Some(1_699_999_996_000));
+
+        // The device clock ran 250ms fast
+        assert_eq!(clock.sync(10_000, 1_700_000_004_750), Some(-250));
+        assert_eq!(clock.to_unix_ms(1000), Some(1_699_999_995_750));
+
+        clock.shift(100);
+        assert_eq!(clock.to_unix_ms(1100), Some(1_699_999_995_750));
+    }
+}
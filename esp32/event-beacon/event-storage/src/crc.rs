@@ -0,0 +1,33 @@
+/// CRC-16/CCITT-FALSE: polynomial 0x1021, initial value 0xffff, no reflection.
+pub fn crc16(bytes: &[u8]) -> u16 {
+    crc16_update(0xffff, bytes)
+}
+
+/// Continues a CRC-16/CCITT-FALSE over `bytes`, for data read in pieces.
+pub fn crc16_update(crc: u16, bytes: &[u8]) -> u16 {
+    bytes.iter().fold(crc, |crc, &byte| {
+        (0..8).fold(crc ^ (byte as u16) << 8, |crc, _| {
+            if crc & 0x8000 != 0 {
+                crc << 1 ^ 0x1021
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc16_check_value() {
+        assert_eq!(crc16(b"123456789"), 0x29b1);
+        assert_eq!(crc16(&[]), 0xffff);
+    }
+
+    #[test]
+    fn test_crc16_update_matches_single_pass() {
+        assert_eq!(crc16_update(crc16(b"1234"), b"56789"), crc16(b"123456789"));
+    }
+}
@@ -0,0 +1,232 @@
+//! Exponentially decayed activity scores.
+//!
+//! Each score is the fraction of time the input was High, weighted so that
+//! time `half_life` milliseconds ago counts half as much as time just now.
+//! A level holds from one sample until the next. Unlike windowed counts, a
+//! score moves smoothly and costs the same few bytes however long its
+//! half-life is.
+//!
+//! Scores are kept in fixed point, so no floating-point support is needed,
+//! and are reported as `u16` fractions where `u16::MAX` means always High.
+//! A half-life of zero disables its score, which then stays at zero.
+
+use crate::history::History;
+
+/// 1.0 in the Q31 format scores and decay factors are kept in.
+const ONE: u64 = 1 << 31;
+
+/// ln(2) in Q31.
+const LN_2: u64 = 0x58b90bfc;
+
+/// `2^(-1/2^i)` in Q31, for `i` from 1 to 16.
+const ROOTS: [u64; 16] = [
+    0x5a82799a, // 2^(-1/2)
+    0x6ba27e65, // 2^(-1/4)
+    0x75606374, // 2^(-1/8)
+    0x7a92be8b, // 2^(-1/16)
+    0x7d41d96e, // 2^(-1/32)
+    0x7e9f0606, // 2^(-1/64)
+    0x7f4f08ae, // 2^(-1/128)
+    0x7fa765ad, // 2^(-1/256)
+    0x7fd3ab29, // 2^(-1/512)
+    0x7fe9d3a9, // 2^(-1/1024)
+    0x7ff4e959, // 2^(-1/2048)
+    0x7ffa748e, // 2^(-1/4096)
+    0x7ffd3a3f, // 2^(-1/8192)
+    0x7ffe9d1e, // 2^(-1/16384)
+    0x7fff4e8e, // 2^(-1/32768)
+    0x7fffa747, // 2^(-1/65536)
+];
+
+/// `2^(-elapsed / half_life)` in Q31.
+fn decay(elapsed: u64, half_life: u64) -> u64 {
+    if half_life == 0 {
+        return 0;
+    }
+    let halvings = elapsed / half_life;
+    if halvings >= 31 {
+        return 0;
+    }
+
+    // The remainder as a 32-bit binary fraction of a half-life. The top half
+    // is applied one root per set bit, and the bottom half is small enough
+    // that 2^-x is 1 - x ln(2) to well within Q31
+    let fraction = ((elapsed % half_life) as u128 * (1 << 32) / half_life as u128) as u64;
+    let mut factor = ONE >> halvings;
+    for (bit, root) in ROOTS.iter().enumerate() {
+        if fraction & (1 << (31 - bit)) != 0 {
+            factor = (factor * root) >> 31;
+        }
+    }
+    let rest = ONE - (((fraction & 0xffff) * LN_2) >> 32);
+    (factor * rest) >> 31
+}
+
+/// One decayed score per half-life.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Scores<const N: usize> {
+    half_lives: [u64; N],
+    scores: [u64; N],
+    /// Timestamp and level of the latest sample.
+    last: Option<(u64, bool)>,
+}
+
+impl<const N: usize> Scores<N> {
+    /// Creates scores of zero with the given half-lives, in milliseconds.
+    pub const fn new(half_lives: [u64; N]) -> Self {
+        Self {
+            half_lives,
+            scores: [0; N],
+            last: None,
+        }
+    }
+
+    pub fn half_lives(&self) -> &[u64; N] {
+        &self.half_lives
+    }
+
+    pub fn record(&mut self, timestamp: u64, high: bool) {
+        if let Some((last, level)) = self.last {
+            self.hold(timestamp.saturating_sub(last), level);
+        }
+        self.last = Some((timestamp, high));
+    }
+
+    /// Recomputes the scores from `history`. Runs hold one level at a fixed
+    /// cadence, so each costs two steps rather than one per sample.
+    pub fn rebuild<const CAPACITY: usize>(&mut self, history: &History<CAPACITY>) {
+        *self = Self::new(self.half_lives);
+        for run in history.runs() {
+            self.record(run.start, run.high);
+            self.record(run.last(), run.high);
+        }
+    }
+
+    /// Scores as fractions of `u16::MAX`.
+    pub fn values(&self) -> [u16; N] {
+        self.scores
+            .map(|score| ((score * u16::MAX as u64 + ONE / 2) >> 31) as u16)
+    }
+
+    /// Decays every score over `elapsed` milliseconds at `level`.
+    fn hold(&mut self, elapsed: u64, level: bool) {
+        for (score, &half_life) in self.scores.iter_mut().zip(&self.half_lives) {
+            if half_life == 0 {
+                continue;
+            }
+            let factor = decay(elapsed, half_life);
+            *score = (*score * factor) >> 31;
+            if level {
+                *score += ONE - factor;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct XorShift(u64);
+
+    impl XorShift {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+    }
+
+    #[test]
+    fn test_decay_matches_exp2() {
+        for (elapsed, half_life) in [
+            (0, 1000),
+            (500, 1000),
+            (1000, 1000),
+            (1234, 1000),
+            (7, 10_000),
+            (299_999, 300_000),
+            (45_000_000, 3_600_000),
+        ] {
+            let expected = (-(elapsed as f64) / half_life as f64).exp2();
+            let actual = decay(elapsed, half_life) as f64 / ONE as f64;
+            assert!(
+                (expected - actual).abs() < 1e-4,
+                "elapsed={elapsed} half_life={half_life} expected={expected} actual={actual}"
+            );
+        }
+        assert_eq!(decay(31_000, 1000), 0);
+        assert_eq!(decay(1, 0), 0);
+    }
+
+    #[test]
+    fn test_scores_track_floating_point_reference() {
+        let half_lives = [10_000, 300_000, 3_600_000];
+        let mut scores = Scores::new(half_lives);
+        let mut reference = [0f64; 3];
+        let mut random = XorShift(0x1234_5678);
+
+        let mut timestamp = 0;
+        let mut level = false;
+        for _ in 0..20_000 {
+            let elapsed = random.next() % 2000;
+            timestamp += elapsed;
+            for (score, &half_life) in reference.iter_mut().zip(&half_lives) {
+                let factor = (-(elapsed as f64) / half_life as f64).exp2();
+                *score = *score * factor + if level { 1.0 - factor } else { 0.0 };
+            }
+
+            // Bursts of activity with long quiet spells in between
+            level = random.next() % 100 < if level { 95 } else { 3 };
+            scores.record(timestamp, level);
+
+            for (value, expected) in scores.values().iter().zip(reference) {
+                let actual = *value as f64 / u16::MAX as f64;
+                assert!(
+                    (expected - actual).abs() < 1e-4,
+                    "timestamp={timestamp} expected={expected} actual={actual}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_steady_high_saturates() {
+        let mut scores = Scores::new([1000, 60_000]);
+        scores.record(0, true);
+        scores.record(1000, true);
+        assert_eq!(scores.values()[0], u16::MAX / 2 + 1);
+
+        scores.record(100_000, true);
+        assert_eq!(scores.values()[0], u16::MAX);
+        assert!(scores.values()[1] > u16::MAX / 2);
+    }
+
+    #[test]
+    fn test_zero_half_life_is_disabled() {
+        let mut scores = Scores::new([0, 1000]);
+        scores.record(0, true);
+        scores.record(1000, true);
+        scores.record(100_000, true);
+
+        assert_eq!(scores.values(), [0, u16::MAX]);
+    }
+
+    #[test]
+    fn test_rebuild_matches_recording() {
+        let mut history: History<16> = History::new();
+        let mut recorded = Scores::new([1000, 10_000]);
+        for i in 0..200 {
+            let high = (i / 20) % 3 == 0;
+            history.push(i * 100, high);
+            recorded.record(i * 100, high);
+        }
+
+        let mut rebuilt = Scores::new([1000, 10_000]);
+        rebuilt.rebuild(&history);
+        for (rebuilt, recorded) in rebuilt.values().iter().zip(recorded.values()) {
+            assert!(rebuilt.abs_diff(recorded) <= 2);
+        }
+    }
+}
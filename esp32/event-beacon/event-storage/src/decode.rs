@@ -0,0 +1,437 @@
+//! Host-side decoders for the encodings in [`crate::report`] and
+//! [`crate::histogram`].
+
+use std::{fmt, vec::Vec};
+
+use crate::{
+    crc::crc16,
+    episodes::Activity,
+    histogram::{self, BINS, Histogram, Histograms},
+    report::{
+        CRC_LEN, FORMAT_ACTIVITY, FORMAT_BUCKETS, FORMAT_COVERAGE, FORMAT_DUTY_CYCLE,
+        FORMAT_STREAKS, HEADER_LEN, NEVER, STREAKS_LEN, VERSION,
+    },
+    statistics::Streaks,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The packet ends before the fields its header describes.
+    Truncated {
+        expected: usize,
+        actual: usize,
+    },
+    /// The CRC does not match the packet contents.
+    Crc {
+        expected: u16,
+        actual: u16,
+    },
+    UnsupportedVersion(u8),
+    /// The format byte names sections this decoder does not know about.
+    UnsupportedFormat(u8),
+    /// The histograms have a different number of bins than this decoder.
+    UnsupportedBinCount(u8),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Truncated { expected, actual } => {
+                write!(f, "report truncated, expected={expected} actual={actual}")
+            }
+            DecodeError::Crc { expected, actual } => {
+                write!(
+                    f,
+                    "report crc mismatch, expected={expected:#06x} actual={actual:#06x}"
+                )
+            }
+            DecodeError::UnsupportedVersion(version) => {
+                write!(f, "unsupported report version={version}")
+            }
+            DecodeError::UnsupportedFormat(format) => {
+                write!(f, "unsupported report format={format:#010b}")
+            }
+            DecodeError::UnsupportedBinCount(bins) => {
+                write!(f, "unsupported histogram bin count={bins}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Window {
+    pub length_ms: u32,
+    pub count: u16,
+    /// Present when the report carries the [`FORMAT_ACTIVITY`] section.
+    pub activity: Option<Activity>,
+    /// Present when the report carries the [`FORMAT_DUTY_CYCLE`] section.
+    pub duty_cycle: Option<u8>,
+    /// Present when the report carries the [`FORMAT_COVERAGE`] section.
+    pub coverage_ms: Option<u32>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DecodedReport {
+    pub version: u8,
+    pub format: u8,
+    pub device_id: u16,
+    pub sequence: u16,
+    pub channel: u8,
+    pub windows: Vec<Window>,
+    /// Present when the report carries the [`FORMAT_STREAKS`] section.
+    pub streaks: Option<Streaks>,
+}
+
+pub fn decode(bytes: &[u8]) -> Result<DecodedReport, DecodeError> {
+    ensure_len(bytes, HEADER_LEN + CRC_LEN)?;
+
+    let version = bytes[0];
+    if version != VERSION {
+        return Err(DecodeError::UnsupportedVersion(version));
+    }
+
+    let format = bytes[1];
+    let known =
+        FORMAT_BUCKETS | FORMAT_ACTIVITY | FORMAT_DUTY_CYCLE | FORMAT_STREAKS | FORMAT_COVERAGE;
+    if format & !known != 0 {
+        return Err(DecodeError::UnsupportedFormat(format));
+    }
+
+    let count = bytes[7] as usize;
+    let mut len = HEADER_LEN + count * 4;
+    let mut section = |bit: u8, section_len: usize| {
+        let start = len;
+        if format & bit != 0 {
+            len += section_len;
+        }
+        start..len
+    };
+    let buckets = section(FORMAT_BUCKETS, count * 2);
+    let activity = section(FORMAT_ACTIVITY, count * 6);
+    let duty_cycle = section(FORMAT_DUTY_CYCLE, count);
+    let streaks = section(FORMAT_STREAKS, STREAKS_LEN);
+    let coverage_ms = section(FORMAT_COVERAGE, count * 4);
+    ensure_len(bytes, len + CRC_LEN)?;
+
+    let expected = u16::from_be_bytes([bytes[len], bytes[len + 1]]);
+    let actual = crc16(&bytes[..len]);
+    if expected != actual {
+        return Err(DecodeError::Crc { expected, actual });
+    }
+
+    let lengths = bytes[HEADER_LEN..HEADER_LEN + count * 4]
+        .chunks_exact(4)
+        .map(read_u32);
+    let counts = bytes[buckets]
+        .chunks_exact(2)
+        .map(|chunk| u16::from_be_bytes([chunk[0], chunk[1]]))
+        .chain(core::iter::repeat(0));
+    let activity = bytes[activity]
+        .chunks_exact(6)
+        .map(|chunk| {
+            Some(Activity {
+                activations: u16::from_be_bytes([chunk[0], chunk[1]]),
+                longest_ms: read_u32(&chunk[2..]),
+            })
+        })
+        .chain(core::iter::repeat(None));
+    let duty_cycle = bytes[duty_cycle]
+        .iter()
+        .map(|&duty_cycle| Some(duty_cycle))
+        .chain(core::iter::repeat(None));
+    let coverage_ms = bytes[coverage_ms]
+        .chunks_exact(4)
+        .map(|chunk| Some(read_u32(chunk)))
+        .chain(core::iter::repeat(None));
+    let streaks = (!streaks.is_empty()).then(|| {
+        let fields = &bytes[streaks];
+        let recency = |value| (value != NEVER).then_some(value);
+        Streaks {
+            longest_high_ms: read_u32(&fields[0..4]),
+            since_high_ms: recency(read_u32(&fields[4..8])),
+            since_low_ms: recency(read_u32(&fields[8..12])),
+        }
+    });
+
+    Ok(DecodedReport {
+        version,
+        format,
+        device_id: u16::from_be_bytes([bytes[2], bytes[3]]),
+        sequence: u16::from_be_bytes([bytes[4], bytes[5]]),
+        channel: bytes[6],
+        windows: lengths
+            .zip(counts)
+            .zip(activity)
+            .zip(duty_cycle)
+            .zip(coverage_ms)
+            .map(
+                |((((length_ms, count), activity), duty_cycle), coverage_ms)| Window {
+                    length_ms,
+                    count,
+                    activity,
+                    duty_cycle,
+                    coverage_ms,
+                },
+            )
+            .collect(),
+        streaks,
+    })
+}
+
+pub fn decode_histograms(bytes: &[u8]) -> Result<Histograms, DecodeError> {
+    ensure_len(bytes, histogram::ENCODED_LEN)?;
+    if bytes[0] as usize != BINS {
+        return Err(DecodeError::UnsupportedBinCount(bytes[0]));
+    }
+
+    let mut counts = bytes[1..histogram::ENCODED_LEN]
+        .chunks_exact(2)
+        .map(|chunk| u16::from_be_bytes([chunk[0], chunk[1]]));
+    let mut histogram = || Histogram {
+        counts: core::array::from_fn(|_| counts.next().unwrap_or(0)),
+    };
+
+    Ok(Histograms {
+        intervals: histogram(),
+        durations: histogram(),
+    })
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn ensure_len(bytes: &[u8], expected: usize) -> Result<(), DecodeError> {
+    if bytes.len() < expected {
+        return Err(DecodeError::Truncated {
+            expected,
+            actual: bytes.len(),
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::Report;
+
+    fn encode(report: &Report) -> Vec<u8> {
+        let mut bytes = std::vec![0; report.encoded_len()];
+        let written = report.encode(&mut bytes).unwrap();
+        bytes.truncate(written);
+        bytes
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let bytes = encode(&Report {
+            device_id: 0xbeef,
+            sequence: 42,
+            channel: 1,
+            breakpoints: &[1000, 5000, 600000],
+            buckets: &[1, 20, 300],
+            activity: None,
+            duty_cycle: None,
+            streaks: None,
+            coverage_ms: None,
+        });
+
+        let decoded = decode(&bytes).unwrap();
+        assert_eq!(decoded.version, VERSION);
+        assert_eq!(decoded.format, FORMAT_BUCKETS);
+        assert_eq!(decoded.device_id, 0xbeef);
+        assert_eq!(decoded.sequence, 42);
+        assert_eq!(decoded.channel, 1);
+        assert_eq!(
+            decoded.windows,
+            [
+                Window {
+                    length_ms: 1000,
+                    count: 1,
+                    activity: None,
+                    duty_cycle: None,
+                    coverage_ms: None
+                },
+                Window {
+                    length_ms: 5000,
+                    count: 20,
+                    activity: None,
+                    duty_cycle: None,
+                    coverage_ms: None
+                },
+                Window {
+                    length_ms: 600000,
+                    count: 300,
+                    activity: None,
+                    duty_cycle: None,
+                    coverage_ms: None
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_round_trip_with_activity() {
+        let activity = [
+            Activity {
+                activations: 5,
+                longest_ms: 100,
+            },
+            Activity {
+                activations: 1,
+                longest_ms: 30_000,
+            },
+        ];
+        let bytes = encode(&Report {
+            device_id: 1,
+            sequence: 2,
+            channel: 0,
+            breakpoints: &[1000, 60000],
+            buckets: &[6, 300],
+            activity: Some(&activity),
+            duty_cycle: None,
+            streaks: None,
+            coverage_ms: None,
+        });
+
+        let decoded = decode(&bytes).unwrap();
+        assert_eq!(decoded.format, FORMAT_BUCKETS | FORMAT_ACTIVITY);
+        assert_eq!(decoded.windows[0].count, 6);
+        assert_eq!(decoded.windows[0].activity, Some(activity[0]));
+        assert_eq!(decoded.windows[1].count, 300);
+        assert_eq!(decoded.windows[1].activity, Some(activity[1]));
+    }
+
+    #[test]
+    fn test_round_trip_with_statistics() {
+        let streaks = Streaks {
+            longest_high_ms: 30_000,
+            since_high_ms: None,
+            since_low_ms: Some(0),
+        };
+        let bytes = encode(&Report {
+            device_id: 1,
+            sequence: 2,
+            channel: 0,
+            breakpoints: &[1000, 60000],
+            buckets: &[6, 300],
+            activity: None,
+            duty_cycle: Some(&[60, 50]),
+            streaks: Some(streaks),
+            coverage_ms: Some(&[1000, 40_000]),
+        });
+
+        let decoded = decode(&bytes).unwrap();
+        assert_eq!(
+            decoded.format,
+            FORMAT_BUCKETS | FORMAT_DUTY_CYCLE | FORMAT_STREAKS | FORMAT_COVERAGE
+        );
+        assert_eq!(decoded.windows[0].activity, None);
+        assert_eq!(decoded.windows[0].duty_cycle, Some(60));
+        assert_eq!(decoded.windows[1].duty_cycle, Some(50));
+        // The second window claims a minute but only 40s have been recorded
+        assert_eq!(decoded.windows[1].coverage_ms, Some(40_000));
+        assert_eq!(decoded.streaks, Some(streaks));
+    }
+
+    #[test]
+    fn test_round_trip_without_windows() {
+        let bytes = encode(&Report {
+            device_id: 1,
+            sequence: 2,
+            channel: 3,
+            breakpoints: &[],
+            buckets: &[],
+            activity: None,
+            duty_cycle: None,
+            streaks: None,
+            coverage_ms: None,
+        });
+
+        assert!(decode(&bytes).unwrap().windows.is_empty());
+    }
+
+    #[test]
+    fn test_corruption_is_detected() {
+        let mut bytes = encode(&Report {
+            device_id: 1,
+            sequence: 2,
+            channel: 0,
+            breakpoints: &[1000],
+            buckets: &[5],
+            activity: None,
+            duty_cycle: None,
+            streaks: None,
+            coverage_ms: None,
+        });
+        bytes[13] ^= 0x01;
+
+        assert!(matches!(decode(&bytes), Err(DecodeError::Crc { .. })));
+    }
+
+    #[test]
+    fn test_truncation_and_unknown_versions_are_rejected() {
+        let bytes = encode(&Report {
+            device_id: 1,
+            sequence: 2,
+            channel: 0,
+            breakpoints: &[1000, 5000],
+            buckets: &[5, 6],
+            activity: None,
+            duty_cycle: None,
+            streaks: None,
+            coverage_ms: None,
+        });
+
+        assert_eq!(
+            decode(&bytes[..bytes.len() - 1]),
+            Err(DecodeError::Truncated {
+                expected: bytes.len(),
+                actual: bytes.len() - 1
+            })
+        );
+
+        let mut future = bytes.clone();
+        future[0] = VERSION + 1;
+        assert_eq!(
+            decode(&future),
+            Err(DecodeError::UnsupportedVersion(VERSION + 1))
+        );
+
+        let mut unknown = bytes;
+        unknown[1] |= 0b1000_0000;
+        assert_eq!(
+            decode(&unknown),
+            Err(DecodeError::UnsupportedFormat(0b1000_0001))
+        );
+    }
+
+    #[test]
+    fn test_histograms_round_trip() {
+        let mut histograms = Histograms::default();
+        histograms.intervals.record(1000);
+        histograms.intervals.record(1000);
+        histograms.durations.record(200);
+
+        let mut bytes = [0; histogram::ENCODED_LEN];
+        histograms.encode(&mut bytes).unwrap();
+        assert_eq!(decode_histograms(&bytes), Ok(histograms));
+
+        assert_eq!(
+            decode_histograms(&bytes[..histogram::ENCODED_LEN - 1]),
+            Err(DecodeError::Truncated {
+                expected: histogram::ENCODED_LEN,
+                actual: histogram::ENCODED_LEN - 1
+            })
+        );
+        bytes[0] = 8;
+        assert_eq!(
+            decode_histograms(&bytes),
+            Err(DecodeError::UnsupportedBinCount(8))
+        );
+    }
+}
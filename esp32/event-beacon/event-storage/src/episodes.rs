@@ -0,0 +1,208 @@
+use crate::history::{Block, History};
+
+/// A level change between two consecutive samples.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Edge {
+    Rising,
+    Falling,
+}
+
+impl Edge {
+    /// The edge between samples at `previous` and `high`, if the level changed.
+    /// Inputs are assumed to start out Low.
+    pub fn between(previous: Option<bool>, high: bool) -> Option<Edge> {
+        match (previous.unwrap_or(false), high) {
+            (false, true) => Some(Edge::Rising),
+            (true, false) => Some(Edge::Falling),
+            _ => None,
+        }
+    }
+}
+
+/// An unbroken stretch of High samples.
+///
+/// Timestamps are milliseconds. `end` is the first Low sample after the
+/// episode, or `None` while the input is still High.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Episode {
+    pub start: u64,
+    pub last_high: u64,
+    pub end: Option<u64>,
+}
+
+impl Episode {
+    /// Time from the rising edge to the falling edge, or to the most recent
+    /// sample while the episode is still going.
+    pub fn duration(&self) -> u64 {
+        self.end.unwrap_or(self.last_high) - self.start
+    }
+}
+
+/// Activations and episode lengths within one window.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Activity {
+    /// Rising edges inside the window.
+    pub activations: u16,
+    /// Duration in milliseconds of the longest episode with a High sample
+    /// inside the window. Episodes are measured in full, even when they
+    /// started before the window did.
+    pub longest_ms: u32,
+}
+
+/// Episodes in `history` from newest to oldest.
+///
+/// Consecutive High runs belong to the same episode. An episode that began
+/// before the oldest run still in history starts at that run.
+pub fn episodes<const CAPACITY: usize>(
+    history: &History<CAPACITY>,
+) -> impl Iterator<Item = Episode> + '_ {
+    let mut runs = history.runs().rev().peekable();
+    let mut end = None;
+
+    core::iter::from_fn(move || {
+        loop {
+            let run = runs.next()?;
+            if !run.high {
+                end = Some(run.start);
+                continue;
+            }
+
+            let last_high = run.last();
+            let mut first = run;
+            while let Some(previous) = runs.next_if(|previous| previous.high) {
+                first = previous;
+            }
+
+            return Some(Episode {
+                start: first.start,
+                last_high,
+                end: end.take(),
+            });
+        }
+    })
+}
+
+/// Activity in each window ending at the most recent sample in `history`.
+pub fn activity<const CAPACITY: usize, const WINDOWS: usize>(
+    history: &History<CAPACITY>,
+    breakpoints: &[u64; WINDOWS],
+) -> [Activity; WINDOWS] {
+    let mut activity = [Activity::default(); WINDOWS];
+    let Some(now) = history.blocks().next_back().map(Block::last) else {
+        return activity;
+    };
+    let cutoffs = breakpoints.map(|breakpoint| (now + 1).saturating_sub(breakpoint));
+    let oldest_cutoff = cutoffs.iter().copied().min().unwrap_or(now + 1);
+
+    for episode in episodes(history).take_while(|episode| episode.last_high >= oldest_cutoff) {
+        let duration = u32::try_from(episode.duration()).unwrap_or(u32::MAX);
+        for (activity, cutoff) in activity.iter_mut().zip(cutoffs) {
+            if episode.last_high < cutoff {
+                continue;
+            }
+            if episode.start >= cutoff {
+                activity.activations = activity.activations.saturating_add(1);
+            }
+            activity.longest_ms = activity.longest_ms.max(duration);
+        }
+    }
+
+    activity
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history_from(samples: &[(u64, bool)]) -> History<16> {
+        let mut history = History::new();
+        for &(timestamp, high) in samples {
+            history.push(timestamp, high);
+        }
+        history
+    }
+
+    #[test]
+    fn test_edges_follow_level_changes() {
+        assert_eq!(Edge::between(None, true), Some(Edge::Rising));
+        assert_eq!(Edge::between(None, false), None);
+        assert_eq!(Edge::between(Some(true), false), Some(Edge::Falling));
+        assert_eq!(Edge::between(Some(true), true), None);
+    }
+
+    #[test]
+    fn test_jittery_runs_form_one_episode() {
+        let history = history_from(&[
+            (0, false),
+            (100, true),
+            (200, true),
+            (301, true),
+            (400, false),
+            (500, true),
+        ]);
+
+        let mut found = episodes(&history);
+        assert_eq!(
+            found.next(),
+            Some(Episode {
+                start: 500,
+                last_high: 500,
+                end: None
+            })
+        );
+        assert_eq!(
+            found.next(),
+            Some(Episode {
+                start: 100,
+                last_high: 300,
+                end: Some(400)
+            })
+        );
+        assert_eq!(found.next(), None);
+    }
+
+    #[test]
+    fn test_many_short_activations_differ_from_one_long_one() {
+        let mut short = [(0, false); 10];
+        for (i, sample) in short.iter_mut().enumerate() {
+            *sample = (i as u64 * 100, i % 2 == 1);
+        }
+        let short = history_from(&short);
+        assert_eq!(
+            activity(&short, &[1000]),
+            [Activity {
+                activations: 5,
+                longest_ms: 100
+            }]
+        );
+
+        let long = history_from(&[(0, true), (30_000, true), (30_100, false)]);
+        assert_eq!(
+            activity(&long, &[1000, 60_000]),
+            [
+                Activity {
+                    activations: 0,
+                    longest_ms: 30_100
+                },
+                Activity {
+                    activations: 1,
+                    longest_ms: 30_100
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_old_episodes_leave_windows() {
+        let history = history_from(&[(0, true), (100, false), (5000, false)]);
+
+        assert_eq!(activity(&history, &[1000]), [Activity::default()]);
+        assert_eq!(
+            activity(&history, &[5001]),
+            [Activity {
+                activations: 1,
+                longest_ms: 100
+            }]
+        );
+    }
+}
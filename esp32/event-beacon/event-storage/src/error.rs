@@ -0,0 +1,29 @@
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The output buffer does not have room for the serialized report.
+    BufferLength { expected: usize, actual: usize },
+    /// No channel exists at this index.
+    UnknownChannel(usize),
+    /// The report has more windows than its encoding can describe.
+    TooManyWindows(usize),
+    /// The snapshot is truncated, has an unknown version or was taken with a
+    /// different number of channels.
+    InvalidSnapshot,
+    /// No window exists at this index.
+    UnknownBucket(usize),
+    /// Every alert rule slot is already taken.
+    TooManyRules,
+    /// Every alert subscriber or report receiver slot is already taken.
+    TooManySubscribers,
+    /// The staging queue is full, so the sample was dropped.
+    StagingFull,
+    /// The stored bonds are truncated, have an unknown version or flags, or
+    /// hold more bonds than fit.
+    InvalidBonds,
+    /// The snapshot has more channels than its encoding can describe.
+    TooManyChannels(usize),
+    /// A channel's history has more blocks than the snapshot can describe.
+    TooManyBlocks(usize),
+    /// A report section does not hold exactly one entry per window.
+    SectionLength { expected: usize, actual: usize },
+}
@@ -0,0 +1,196 @@
+//! Paged export of a channel's raw history.
+//!
+//! Blocks are numbered in the order they started, so a transport can send the
+//! history in pages that fit its MTU, remember the [`Page::next`] sequence
+//! number the reader acknowledged and resume from it after a disconnect.
+//!
+//! | offset | size     | field                                          |
+//! |--------|----------|------------------------------------------------|
+//! | 0      | 8        | sequence number of the first block, big-endian |
+//! | 8      | 1        | block count `n`                                |
+//! | 9      | 1        | flags                                          |
+//! | 10     | `47 * n` | blocks, oldest first, as in [`Block::encode`]  |
+//!
+//! Block starts are milliseconds since the Unix epoch when the flags include
+//! [`FLAG_UNIX_TIME`], and stored timestamps otherwise. Snapshots keep the
+//! sequence numbers, so a reader can resume across a reset of the device.
+
+use crate::{
+    Error,
+    clock::Clock,
+    history::{Block, History},
+};
+
+pub const PAGE_HEADER_LEN: usize = 10;
+
+/// Block starts have been converted to Unix time.
+pub const FLAG_UNIX_TIME: u8 = 0b0000_0001;
+
+/// Where a page of history starts and where the next one should.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Page {
+    /// Sequence number of the first block in the page. It is later than the
+    /// one asked for if those blocks have already been evicted.
+    pub first: u64,
+    /// Number of blocks in the page.
+    pub blocks: usize,
+    /// Sequence number to ask for next. The newest block is still growing, so
+    /// a page that ends with it points back at it rather than past it.
+    pub next: u64,
+    /// Number of bytes written.
+    pub len: usize,
+}
+
+/// Writes as many blocks from `sequence` onwards as fit into `bytes`, with
+/// their starts converted to Unix time if `clock` has been synced.
+pub fn write_page<const CAPACITY: usize>(
+    history: &History<CAPACITY>,
+    sequence: u64,
+    clock: Clock,
+    bytes: &mut [u8],
+) -> Result<Page, Error> {
+    let expected = PAGE_HEADER_LEN + Block::ENCODED_LEN;
+    if bytes.len() < expected {
+        return Err(Error::BufferLength {
+            expected,
+            actual: bytes.len(),
+        });
+    }
+
+    let end = history.last_sequence().map_or(0, |last| last + 1);
+    let first = sequence.clamp(history.first_sequence(), end);
+    let room = ((bytes.len() - PAGE_HEADER_LEN) / Block::ENCODED_LEN).min(u8::MAX as usize);
+
+    let mut blocks = 0;
+    let mut next = first;
+    for (sequence, block) in history.since(first).take(room) {
+        let mut block = *block;
+        block.start = clock.to_unix_ms(block.start).unwrap_or(block.start);
+        let offset = PAGE_HEADER_LEN + blocks * Block::ENCODED_LEN;
+        block.encode(&mut bytes[offset..offset + Block::ENCODED_LEN]);
+        blocks += 1;
+        if Some(sequence) != history.last_sequence() {
+            next = sequence + 1;
+        }
+    }
+
+    bytes[0..8].copy_from_slice(&first.to_be_bytes());
+    bytes[8] = blocks as u8;
+    bytes[9] = if clock.is_synced() { FLAG_UNIX_TIME } else { 0 };
+
+    Ok(Page {
+        first,
+        blocks,
+        next,
+        len: PAGE_HEADER_LEN + blocks * Block::ENCODED_LEN,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE_LEN: usize = PAGE_HEADER_LEN + 2 * Block::ENCODED_LEN;
+
+    /// Sample times whose gaps double each time, so every block gets two
+    /// samples before the next one no longer fits its cadence.
+    fn spaced(i: u64) -> u64 {
+        100 * ((1 << i) - 1)
+    }
+
+    /// Pushes blocks numbered `from` to `to`, each a High and a Low sample.
+    fn blocks(history: &mut History<4>, from: u64, to: u64) {
+        for i in 2 * from..2 * to {
+            history.push(spaced(i), i % 2 == 0);
+        }
+    }
+
+    fn starts(bytes: &[u8], page: &Page) -> heapless::Vec<u64, 4> {
+        bytes[PAGE_HEADER_LEN..page.len]
+            .chunks_exact(Block::ENCODED_LEN)
+            .map(|chunk| Block::decode(chunk).unwrap().start)
+            .collect()
+    }
+
+    #[test]
+    fn test_pages_resume_from_next() {
+        let mut history: History<4> = History::new();
+        blocks(&mut history, 0, 5);
+
+        let mut bytes = [0; PAGE_LEN];
+        let page = write_page(&history, 0, Clock::new(), &mut bytes).unwrap();
+        assert_eq!(
+            page,
+            Page {
+                first: 0,
+                blocks: 2,
+                next: 2,
+                len: PAGE_LEN,
+            }
+        );
+        assert_eq!(&bytes[..10], &[0, 0, 0, 0, 0, 0, 0, 0, 2, 0]);
+        assert_eq!(starts(&bytes, &page).as_slice(), &[0, spaced(2)]);
+
+        let page = write_page(&history, page.next, Clock::new(), &mut bytes).unwrap();
+        assert_eq!(starts(&bytes, &page).as_slice(), &[spaced(4), spaced(6)]);
+
+        // The open block is sent but asked for again
+        let page = write_page(&history, page.next, Clock::new(), &mut bytes).unwrap();
+        assert_eq!((page.first, page.blocks, page.next), (4, 1, 4));
+        assert_eq!(starts(&bytes, &page).as_slice(), &[spaced(8)]);
+    }
+
+    #[test]
+    fn test_pages_skip_evicted_blocks_after_wraparound() {
+        let mut history: History<4> = History::new();
+        blocks(&mut history, 0, 3);
+
+        let mut bytes = [0; PAGE_LEN];
+        let page = write_page(&history, 0, Clock::new(), &mut bytes).unwrap();
+        assert_eq!(page.next, 2);
+
+        // The reader disconnects while the buffer wraps around twice
+        blocks(&mut history, 3, 13);
+        let page = write_page(&history, page.next, Clock::new(), &mut bytes).unwrap();
+        assert_eq!((page.first, page.blocks, page.next), (8, 2, 10));
+        assert_eq!(&bytes[..8], &8u64.to_be_bytes());
+        assert_eq!(starts(&bytes, &page).as_slice(), &[spaced(16), spaced(18)]);
+
+        // Asking past the newest block returns an empty page
+        let page = write_page(&history, 20, Clock::new(), &mut bytes).unwrap();
+        assert_eq!((page.first, page.blocks, page.next), (13, 0, 13));
+    }
+
+    #[test]
+    fn test_synced_pages_carry_unix_time() {
+        let mut history: History<4> = History::new();
+        blocks(&mut history, 0, 2);
+        let mut clock = Clock::new();
+        clock.sync(100, 1_700_000_000_000);
+
+        let mut bytes = [0; PAGE_LEN];
+        let page = write_page(&history, 0, clock, &mut bytes).unwrap();
+        assert_eq!(bytes[9], FLAG_UNIX_TIME);
+        assert_eq!(
+            starts(&bytes, &page).as_slice(),
+            &[1_699_999_999_900, 1_700_000_000_200]
+        );
+    }
+
+    #[test]
+    fn test_page_needs_room_for_a_block() {
+        let history: History<4> = History::new();
+
+        assert_eq!(
+            write_page(&history, 0, Clock::new(), &mut [0; PAGE_HEADER_LEN]),
+            Err(Error::BufferLength {
+                expected: PAGE_HEADER_LEN + Block::ENCODED_LEN,
+                actual: PAGE_HEADER_LEN
+            })
+        );
+        assert_eq!(
+            write_page(&history, 0, Clock::new(), &mut [0; PAGE_LEN]).map(|page| page.len),
+            Ok(PAGE_HEADER_LEN)
+        );
+    }
+}
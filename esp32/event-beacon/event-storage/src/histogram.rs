@@ -0,0 +1,135 @@
+//! Log-scaled histograms of activation timing.
+//!
+//! Bin 0 holds values below [`FIRST_BIN_MS`], and each following bin is
+//! twice as wide as the one before, so 16 bins cover about half an hour with
+//! the last bin collecting everything longer. Counts saturate at `u16::MAX`.
+//!
+//! [`Histograms::encode`] writes a bin count byte followed by the interval
+//! counts and then the duration counts, each a big-endian `u16`.
+
+use crate::Error;
+
+pub const BINS: usize = 16;
+/// Upper bound, exclusive, of the first bin in milliseconds.
+pub const FIRST_BIN_MS: u64 = 128;
+pub const ENCODED_LEN: usize = 1 + 2 * BINS * 2;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Histogram {
+    pub counts: [u16; BINS],
+}
+
+impl Histogram {
+    /// Index of the bin that `milliseconds` falls into.
+    pub fn bin(milliseconds: u64) -> usize {
+        let scaled = milliseconds / FIRST_BIN_MS;
+        let bin = (u64::BITS - scaled.leading_zeros()) as usize;
+        bin.min(BINS - 1)
+    }
+
+    /// Smallest value, in milliseconds, that falls into `bin`.
+    pub fn lower_bound(bin: usize) -> u64 {
+        match bin {
+            0 => 0,
+            _ => FIRST_BIN_MS << (bin - 1),
+        }
+    }
+
+    pub fn record(&mut self, milliseconds: u64) {
+        let count = &mut self.counts[Self::bin(milliseconds)];
+        *count = count.saturating_add(1);
+    }
+
+    pub fn total(&self) -> u32 {
+        self.counts.iter().map(|&count| count as u32).sum()
+    }
+}
+
+/// Timing of a channel's activations since the histograms were last reset.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Histograms {
+    /// Time between consecutive rising edges.
+    pub intervals: Histogram,
+    /// Time from a rising edge to the falling edge that ends the episode.
+    pub durations: Histogram,
+}
+
+impl Histograms {
+    /// Encodes both histograms into `bytes` and returns the number of bytes
+    /// written.
+    pub fn encode(&self, bytes: &mut [u8]) -> Result<usize, Error> {
+        if bytes.len() < ENCODED_LEN {
+            return Err(Error::BufferLength {
+                expected: ENCODED_LEN,
+                actual: bytes.len(),
+            });
+        }
+
+        bytes[0] = BINS as u8;
+        let counts = self.intervals.counts.iter().chain(&self.durations.counts);
+        for (chunk, count) in bytes[1..ENCODED_LEN].chunks_exact_mut(2).zip(counts) {
+            chunk.copy_from_slice(&count.to_be_bytes());
+        }
+
+        Ok(ENCODED_LEN)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bins_double_in_width() {
+        assert_eq!(Histogram::bin(0), 0);
+        assert_eq!(Histogram::bin(127), 0);
+        assert_eq!(Histogram::bin(128), 1);
+        assert_eq!(Histogram::bin(255), 1);
+        assert_eq!(Histogram::bin(256), 2);
+        assert_eq!(Histogram::bin(1000), 3);
+        assert_eq!(Histogram::bin(60_000), 9);
+        assert_eq!(Histogram::bin(u64::MAX), BINS - 1);
+
+        for bin in 0..BINS {
+            assert_eq!(Histogram::bin(Histogram::lower_bound(bin)), bin);
+        }
+        for bin in 1..BINS {
+            assert_eq!(Histogram::bin(Histogram::lower_bound(bin) - 1), bin - 1);
+        }
+    }
+
+    #[test]
+    fn test_counts_saturate() {
+        let mut histogram = Histogram::default();
+        histogram.counts[3] = u16::MAX;
+        histogram.record(1000);
+        histogram.record(0);
+
+        assert_eq!(histogram.counts[3], u16::MAX);
+        assert_eq!(histogram.total(), u16::MAX as u32 + 1);
+    }
+
+    #[test]
+    fn test_encode() {
+        let mut histograms = Histograms::default();
+        histograms.intervals.record(1000);
+        histograms.durations.record(0);
+        histograms.durations.record(u64::MAX);
+
+        let mut bytes = [0xff; ENCODED_LEN];
+        assert_eq!(histograms.encode(&mut bytes), Ok(ENCODED_LEN));
+        assert_eq!(bytes[0], 16);
+        assert_eq!(&bytes[1 + 3 * 2..1 + 4 * 2], &[0, 1]);
+        assert_eq!(&bytes[1 + BINS * 2..1 + BINS * 2 + 2], &[0, 1]);
+        assert_eq!(&bytes[ENCODED_LEN - 2..], &[0, 1]);
+        assert_eq!(bytes.iter().map(|&byte| byte as u32).sum::<u32>(), 16 + 3);
+
+        assert_eq!(
+            histograms.encode(&mut [0; ENCODED_LEN - 1]),
+            Err(Error::BufferLength {
+                expected: ENCODED_LEN,
+                actual: ENCODED_LEN - 1
+            })
+        );
+    }
+}
@@ -0,0 +1,721 @@
+use core::ops::Range;
+
+use heapless::HistoryBuf;
+
+/// Samples a [`Block`] holds one level bit for. A steady block, whose samples
+/// all share a level, stores that level once and can grow past this.
+pub const BLOCK_SAMPLES: u16 = 256;
+
+const BLOCK_BYTES: usize = BLOCK_SAMPLES as usize / 8;
+
+/// Flag in [`Block::encode`]'s format: every sample has the level of bit 0.
+const FLAG_STEADY: u8 = 0b0000_0001;
+
+/// Samples taken on a regular cadence, packed as one level bit each.
+///
+/// Timestamps are milliseconds since boot. A sample joins the block when it
+/// lands within half an `interval` of the next slot and is stored at that
+/// slot, so jitter in when samples are taken does not split blocks and the
+/// timestamp of sample `n` is `start + n * interval`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Block {
+    pub start: u64,
+    pub interval: u32,
+    pub count: u16,
+    /// Whether every sample has the level of the first, in which case
+    /// `levels` holds only that one bit.
+    steady: bool,
+    /// Bit `n % 8` of byte `n / 8` is the level of sample `n`.
+    levels: [u8; BLOCK_BYTES],
+}
+
+impl Block {
+    /// Size of a block in [`Block::encode`]'s format.
+    pub const ENCODED_LEN: usize = 15 + BLOCK_BYTES;
+
+    fn new(timestamp: u64, high: bool) -> Self {
+        let mut levels = [0; BLOCK_BYTES];
+        levels[0] = high as u8;
+
+        Self {
+            start: timestamp,
+            interval: 0,
+            count: 1,
+            steady: true,
+            levels,
+        }
+    }
+
+    /// Timestamp of the most recent sample in the block.
+    pub fn last(&self) -> u64 {
+        self.start + self.interval as u64 * (self.count as u64 - 1)
+    }
+
+    /// Level of sample `index`, counting from the oldest.
+    pub fn level(&self, index: u16) -> bool {
+        let index = if self.steady { 0 } else { index as usize };
+        self.levels[index / 8] >> (index % 8) & 1 != 0
+    }
+
+    fn set_level(&mut self, index: u16, high: bool) {
+        let (byte, bit) = (index as usize / 8, index % 8);
+        self.levels[byte] = self.levels[byte] & !(1 << bit) | (high as u8) << bit;
+    }
+
+    /// Whether pushing samples could have built the block: it has at least
+    /// one sample, a nonzero interval if it has more, a last sample that fits
+    /// in a `u64`, and a level bit for every sample unless it is steady.
+    pub fn is_valid(&self) -> bool {
+        let span_fits = match self.count {
+            0 => false,
+            1 => true,
+            count => {
+                self.interval > 0
+                    && (self.interval as u64)
+                        .checked_mul(count as u64 - 1)
+                        .and_then(|span| self.start.checked_add(span))
+                        .is_some()
+            }
+        };
+
+        span_fits && (self.steady || self.count <= BLOCK_SAMPLES)
+    }
+
+    /// Number of samples in the block taken at or after `cutoff`.
+    pub fn count_since(&self, cutoff: u64) -> u16 {
+        if self.start >= cutoff {
+            self.count
+        } else if self.last() < cutoff {
+            0
+        } else {
+            let skipped = (cutoff - self.start).div_ceil(self.interval as u64);
+            self.count - skipped as u16
+        }
+    }
+
+    /// Number of High samples among those at `indices`.
+    pub fn count_high(&self, indices: Range<u16>) -> u16 {
+        if self.steady {
+            if self.level(0) {
+                indices.len() as u16
+            } else {
+                0
+            }
+        } else {
+            indices.filter(|&index| self.level(index)).count() as u16
+        }
+    }
+
+    /// The block split into runs of samples with the same level, oldest first.
+    pub fn runs(&self) -> Runs<'_> {
+        Runs {
+            block: self,
+            front: 0,
+            back: self.count,
+        }
+    }
+
+    /// Writes the block as start `u64`, interval `u32`, count `u16`, flags
+    /// `u8` and the level bits, all big-endian. `bytes` must hold
+    /// [`Block::ENCODED_LEN`] bytes.
+    pub fn encode(&self, bytes: &mut [u8]) {
+        bytes[0..8].copy_from_slice(&self.start.to_be_bytes());
+        bytes[8..12].copy_from_slice(&self.interval.to_be_bytes());
+        bytes[12..14].copy_from_slice(&self.count.to_be_bytes());
+        bytes[14] = if self.steady { FLAG_STEADY } else { 0 };
+        bytes[15..Self::ENCODED_LEN].copy_from_slice(&self.levels);
+    }
+
+    /// Reads a block written by [`Block::encode`], or `None` if it sets flags
+    /// this version does not know. Check [`Block::is_valid`] before trusting it.
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        if bytes[14] & !FLAG_STEADY != 0 {
+            return None;
+        }
+
+        let mut start = [0; 8];
+        start.copy_from_slice(&bytes[0..8]);
+        let mut levels = [0; BLOCK_BYTES];
+        levels.copy_from_slice(&bytes[15..Self::ENCODED_LEN]);
+
+        Some(Self {
+            start: u64::from_be_bytes(start),
+            interval: u32::from_be_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]),
+            count: u16::from_be_bytes([bytes[12], bytes[13]]),
+            steady: bytes[14] & FLAG_STEADY != 0,
+            levels,
+        })
+    }
+
+    /// Whether another sample at level `high` fits, wherever it lands.
+    fn has_room(&self, high: bool) -> bool {
+        if self.steady && high == self.level(0) {
+            self.count < u16::MAX
+        } else {
+            self.count < BLOCK_SAMPLES
+        }
+    }
+
+    /// Slot a sample at `timestamp` would take in the block, if it lands
+    /// close enough to one. The second sample sets the interval, snapping to
+    /// `cadence` if it is close to that.
+    fn slot(&self, timestamp: u64, cadence: Option<u32>) -> Option<u64> {
+        if self.count == 1 {
+            let gap = timestamp.checked_sub(self.start)?;
+            let gap = u32::try_from(gap).ok().filter(|&gap| gap > 0)?;
+            let interval = match cadence {
+                Some(cadence) if gap.abs_diff(cadence) <= cadence / 2 => cadence,
+                _ => gap,
+            };
+            Some(self.start + interval as u64)
+        } else {
+            let next = self.last().checked_add(self.interval as u64)?;
+            (timestamp.abs_diff(next) <= self.interval as u64 / 2).then_some(next)
+        }
+    }
+
+    fn extend(&mut self, slot: u64, high: bool) {
+        if self.count == 1 {
+            self.interval = (slot - self.start) as u32;
+        }
+        if self.steady && high != self.level(0) {
+            let level = self.level(0);
+            self.steady = false;
+            for index in 1..self.count {
+                self.set_level(index, level);
+            }
+        }
+        if !self.steady {
+            self.set_level(self.count, high);
+        }
+        self.count += 1;
+    }
+
+    /// Interval the samples after the block most likely follow, given the
+    /// first of them arrived at `next`. Jitter that drifts the samples out
+    /// of step with the block's slots shows up in the average interval.
+    fn cadence(&self, next: u64) -> Option<u32> {
+        if self.count < 2 {
+            return None;
+        }
+
+        let gap = next.checked_sub(self.last())?;
+        if gap < 2 * self.interval as u64 {
+            let span = next - self.start;
+            u32::try_from((span + self.count as u64 / 2) / self.count as u64).ok()
+        } else {
+            Some(self.interval)
+        }
+    }
+}
+
+/// Consecutive samples with the same level, as split out of a [`Block`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Run {
+    pub start: u64,
+    pub interval: u32,
+    pub count: u16,
+    pub high: bool,
+}
+
+impl Run {
+    /// Timestamp of the most recent sample in the run.
+    pub fn last(&self) -> u64 {
+        self.start + self.interval as u64 * (self.count as u64 - 1)
+    }
+}
+
+/// Iterator over the runs in a block, returned by [`Block::runs`].
+pub struct Runs<'a> {
+    block: &'a Block,
+    front: u16,
+    back: u16,
+}
+
+impl Runs<'_> {
+    fn run(&self, from: u16, to: u16) -> Run {
+        Run {
+            start: self.block.start + self.block.interval as u64 * from as u64,
+            interval: self.block.interval,
+            count: to - from,
+            high: self.block.level(from),
+        }
+    }
+}
+
+impl Iterator for Runs<'_> {
+    type Item = Run;
+
+    fn next(&mut self) -> Option<Run> {
+        if self.front >= self.back {
+            return None;
+        }
+
+        let from = self.front;
+        self.front = if self.block.steady {
+            self.back
+        } else {
+            let high = self.block.level(from);
+            (from + 1..self.back)
+                .find(|&index| self.block.level(index) != high)
+                .unwrap_or(self.back)
+        };
+
+        Some(self.run(from, self.front))
+    }
+}
+
+impl DoubleEndedIterator for Runs<'_> {
+    fn next_back(&mut self) -> Option<Run> {
+        if self.front >= self.back {
+            return None;
+        }
+
+        let to = self.back;
+        self.back = if self.block.steady {
+            self.front
+        } else {
+            let high = self.block.level(to - 1);
+            (self.front..to - 1)
+                .rev()
+                .find(|&index| self.block.level(index) != high)
+                .map_or(self.front, |index| index + 1)
+        };
+
+        Some(self.run(self.back, to))
+    }
+}
+
+/// Sample history holding up to `CAPACITY` completed blocks.
+///
+/// Samples taken on a regular cadence cost one bit each whatever their level,
+/// and a steady input costs one block no matter how long it lasts, so memory
+/// scales with how long the cadence holds rather than with transitions.
+/// Every block is numbered in the order it was started, which lets readers
+/// keep a position in the history across pushes.
+pub struct History<const CAPACITY: usize> {
+    closed: HistoryBuf<Block, CAPACITY>,
+    open: Option<Block>,
+    next_sequence: u64,
+    /// Interval the last closed block ended on, used to line the next block
+    /// up with the same cadence.
+    cadence: Option<u32>,
+}
+
+impl<const CAPACITY: usize> Default for History<CAPACITY> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const CAPACITY: usize> History<CAPACITY> {
+    pub const fn new() -> Self {
+        Self {
+            closed: HistoryBuf::new(),
+            open: None,
+            next_sequence: 0,
+            cadence: None,
+        }
+    }
+
+    /// Timestamp a sample taken at `timestamp` would be stored at, which is
+    /// the slot it snaps to if it extends the open block.
+    pub fn align(&self, timestamp: u64, high: bool) -> u64 {
+        self.open
+            .as_ref()
+            .filter(|block| block.has_room(high))
+            .and_then(|block| block.slot(timestamp, self.cadence))
+            .unwrap_or(timestamp)
+    }
+
+    /// Adds a sample, returning the oldest block if it had to be evicted to make room.
+    pub fn push(&mut self, timestamp: u64, high: bool) -> Option<Block> {
+        let mut evicted = None;
+
+        if let Some(block) = self.open.as_mut() {
+            if block.has_room(high)
+                && let Some(slot) = block.slot(timestamp, self.cadence)
+            {
+                block.extend(slot, high);
+                return None;
+            }
+
+            self.cadence = block.cadence(timestamp).or(self.cadence);
+            if self.closed.is_full() {
+                evicted = self.closed.oldest().copied();
+            }
+            self.closed.write(*block);
+        }
+
+        self.open = Some(Block::new(timestamp, high));
+        self.next_sequence += 1;
+
+        evicted
+    }
+
+    /// Replaces the history with `blocks`, oldest first, numbering them from
+    /// `first_sequence` on. The newest block stays open, and older blocks are
+    /// dropped if there are more than fit.
+    pub fn restore(&mut self, first_sequence: u64, blocks: impl IntoIterator<Item = Block>) {
+        *self = Self::new();
+        self.next_sequence = first_sequence;
+
+        for block in blocks {
+            if let Some(open) = self.open.replace(block) {
+                self.closed.write(open);
+            }
+            self.next_sequence += 1;
+        }
+    }
+
+    /// Sequence number of the oldest block still in history.
+    pub fn first_sequence(&self) -> u64 {
+        self.next_sequence - self.len() as u64
+    }
+
+    /// Sequence number of the newest block, which is the one still accepting samples.
+    pub fn last_sequence(&self) -> Option<u64> {
+        self.next_sequence.checked_sub(1)
+    }
+
+    /// Number of blocks in history, including the open one.
+    pub fn len(&self) -> usize {
+        self.closed.len() + self.open.is_some() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.open.is_none()
+    }
+
+    /// Looks up a block by its sequence number.
+    pub fn get(&self, sequence: u64) -> Option<&Block> {
+        let index = usize::try_from(sequence.checked_sub(self.first_sequence())?).ok()?;
+        let (older, newer) = self.closed.as_slices();
+
+        if index < older.len() {
+            older.get(index)
+        } else if index < self.closed.len() {
+            newer.get(index - older.len())
+        } else if index == self.closed.len() {
+            self.open.as_ref()
+        } else {
+            None
+        }
+    }
+
+    /// Blocks from oldest to newest.
+    pub fn blocks(&self) -> impl DoubleEndedIterator<Item = &Block> {
+        self.closed.oldest_ordered().chain(self.open.iter())
+    }
+
+    /// Runs of samples with the same level from oldest to newest. A run that
+    /// spans two blocks comes out as two runs.
+    pub fn runs(&self) -> impl DoubleEndedIterator<Item = Run> {
+        self.blocks().flat_map(Block::runs)
+    }
+
+    /// Blocks numbered `sequence` and later, oldest first, with their sequence
+    /// numbers. Starts at the oldest block if `sequence` has been evicted.
+    pub fn since(&self, sequence: u64) -> impl Iterator<Item = (u64, &Block)> {
+        let first = self.first_sequence();
+        let skip = usize::try_from(sequence.saturating_sub(first)).unwrap_or(usize::MAX);
+
+        self.blocks()
+            .enumerate()
+            .skip(skip)
+            .map(move |(index, block)| (first + index as u64, block))
+    }
+
+    /// Number of High samples taken less than `window` milliseconds before `now`.
+    ///
+    /// This rescans every block inside the window; [`crate::windows::Windows`]
+    /// keeps the same counts up to date incrementally.
+    pub fn count_high(&self, now: u64, window: u64) -> u32 {
+        let cutoff = (now + 1).saturating_sub(window);
+
+        self.blocks()
+            .rev()
+            .take_while(|block| block.last() >= cutoff)
+            .map(|block| {
+                block.count_high(block.count - block.count_since(cutoff)..block.count) as u32
+            })
+            .sum()
+    }
+
+    /// Number of samples of either level taken less than `window` milliseconds
+    /// before `now`.
+    pub fn count_samples(&self, now: u64, window: u64) -> u32 {
+        let cutoff = (now + 1).saturating_sub(window);
+
+        self.blocks()
+            .rev()
+            .take_while(|block| block.last() >= cutoff)
+            .map(|block| block.count_since(cutoff) as u32)
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sample times whose gaps double each time, so every block gets two
+    /// samples before the next one no longer fits its cadence.
+    fn spaced(i: u64) -> u64 {
+        100 * ((1 << i) - 1)
+    }
+
+    #[test]
+    fn test_regular_samples_share_a_block() {
+        let mut history: History<4> = History::new();
+
+        for i in 0..1000 {
+            history.push(i * 100, true);
+        }
+
+        assert_eq!(history.len(), 1);
+        let runs = history.runs().collect::<heapless::Vec<Run, 4>>();
+        assert_eq!(
+            runs.as_slice(),
+            &[Run {
+                start: 0,
+                interval: 100,
+                count: 1000,
+                high: true
+            }]
+        );
+    }
+
+    #[test]
+    fn test_jittery_toggling_shares_a_block() {
+        let mut history: History<4> = History::new();
+
+        // The second sample sets the interval, so it lands on time
+        for i in 0..BLOCK_SAMPLES as u64 {
+            let jitter = if i < 2 { 0 } else { i * 37 % 41 };
+            assert_eq!(history.align(i * 100 + jitter, i % 2 == 0), i * 100);
+            history.push(i * 100 + jitter, i % 2 == 0);
+        }
+
+        assert_eq!(history.len(), 1);
+        assert_eq!(history.count_high(25_500, 100_000), 128);
+        assert_eq!(history.runs().count(), 256);
+        assert!(history.runs().enumerate().all(|(i, run)| {
+            run.start == i as u64 * 100 && run.count == 1 && run.high == (i % 2 == 0)
+        }));
+
+        // The block is full, so the next sample starts another
+        history.push(25_600, true);
+        assert_eq!(history.len(), 2);
+    }
+
+    #[test]
+    fn test_level_change_packs_a_short_steady_block() {
+        let mut history: History<4> = History::new();
+
+        for i in 0..300 {
+            history.push(i * 100, true);
+        }
+        // Too long to pack, so the change starts a new block
+        history.push(30_000, false);
+        for i in 301..310 {
+            history.push(i * 100, i >= 305);
+        }
+
+        assert_eq!(history.len(), 2);
+        let runs = history
+            .runs()
+            .map(|run| (run.start, run.count, run.high))
+            .collect::<heapless::Vec<_, 4>>();
+        assert_eq!(
+            runs.as_slice(),
+            &[(0, 300, true), (30_000, 5, false), (30_500, 5, true)]
+        );
+        assert_eq!(history.runs().rev().nth(1).map(|run| run.count), Some(5));
+    }
+
+    #[test]
+    fn test_missed_samples_keep_the_cadence() {
+        let mut history: History<4> = History::new();
+
+        for i in 0..3 {
+            history.push(i * 100, true);
+        }
+        history.push(400, true);
+
+        assert_eq!(history.len(), 2);
+        // The second sample of the new block snaps to the old cadence
+        assert_eq!(history.align(530, true), 500);
+        history.push(530, true);
+        assert_eq!(history.get(1).map(|block| block.interval), Some(100));
+    }
+
+    #[test]
+    fn test_count_since_splits_block_at_cutoff() {
+        let block = Block {
+            start: 1000,
+            interval: 100,
+            count: 10,
+            ..Block::new(1000, true)
+        };
+
+        assert_eq!(block.count_since(0), 10);
+        assert_eq!(block.count_since(1000), 10);
+        assert_eq!(block.count_since(1001), 9);
+        assert_eq!(block.count_since(1100), 9);
+        assert_eq!(block.count_since(1900), 1);
+        assert_eq!(block.count_since(1901), 0);
+        assert_eq!(block.count_high(3..10), 7);
+    }
+
+    #[test]
+    fn test_oldest_blocks_are_evicted() {
+        let mut history: History<2> = History::new();
+
+        for i in 0..10 {
+            history.push(spaced(i), i % 2 == 0);
+        }
+
+        // Two closed blocks plus the open one
+        assert_eq!(history.blocks().count(), 3);
+        assert_eq!(history.count_high(spaced(9), u64::MAX), 3);
+        assert_eq!(history.count_samples(spaced(9), u64::MAX), 6);
+        assert_eq!(history.first_sequence(), 2);
+        assert_eq!(history.last_sequence(), Some(4));
+        assert_eq!(history.get(1), None);
+        assert_eq!(history.get(2).map(|block| block.start), Some(spaced(4)));
+        assert_eq!(history.get(4).map(|block| block.start), Some(spaced(8)));
+        assert_eq!(history.get(5), None);
+    }
+
+    fn since(history: &History<3>, sequence: u64) -> heapless::Vec<(u64, u64), 4> {
+        history
+            .since(sequence)
+            .map(|(sequence, block)| (sequence, block.start))
+            .collect()
+    }
+
+    #[test]
+    fn test_since_survives_wraparound() {
+        let mut history: History<3> = History::new();
+        for i in 0..10 {
+            history.push(spaced(i), i % 2 == 0);
+        }
+
+        // Blocks 1 to 3 are closed and 4 is open
+        assert_eq!(
+            since(&history, 0).as_slice(),
+            &[
+                (1, spaced(2)),
+                (2, spaced(4)),
+                (3, spaced(6)),
+                (4, spaced(8))
+            ]
+        );
+        assert_eq!(
+            since(&history, 3).as_slice(),
+            &[(3, spaced(6)), (4, spaced(8))]
+        );
+        assert_eq!(since(&history, 5).as_slice(), &[]);
+
+        history.push(spaced(10), true);
+        assert_eq!(
+            since(&history, 3).as_slice(),
+            &[(3, spaced(6)), (4, spaced(8)), (5, spaced(10))]
+        );
+        assert_eq!(since(&history, 0)[0], (2, spaced(4)));
+    }
+
+    #[test]
+    fn test_encoded_block_round_trips() {
+        let mut history: History<1> = History::new();
+        for i in 0..20 {
+            history.push(0x0102_0304_0506_0708 + i * 100, i % 3 == 0);
+        }
+        let block = *history.get(0).unwrap();
+
+        let mut bytes = [0; Block::ENCODED_LEN];
+        block.encode(&mut bytes);
+        assert_eq!(&bytes[..8], &[1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(Block::decode(&bytes), Some(block));
+
+        bytes[14] |= 0b1000_0000;
+        assert_eq!(Block::decode(&bytes), None);
+    }
+
+    #[test]
+    fn test_blocks_pushing_cannot_build_are_invalid() {
+        let block = Block {
+            start: 1000,
+            interval: 100,
+            count: 3,
+            ..Block::new(1000, false)
+        };
+        assert!(block.is_valid());
+        assert!(Block::new(0, true).is_valid());
+        assert!(
+            Block {
+                count: 1000,
+                ..block
+            }
+            .is_valid()
+        );
+
+        for invalid in [
+            Block { count: 0, ..block },
+            Block {
+                interval: 0,
+                ..block
+            },
+            Block {
+                start: u64::MAX - 100,
+                ..block
+            },
+            Block {
+                count: BLOCK_SAMPLES + 1,
+                steady: false,
+                ..block
+            },
+        ] {
+            assert!(!invalid.is_valid(), "{invalid:?}");
+        }
+    }
+
+    #[test]
+    fn test_restore_keeps_newest_blocks() {
+        let mut source: History<8> = History::new();
+        for i in 0..6 {
+            source.push(spaced(i), i < 3);
+        }
+
+        let mut history: History<1> = History::new();
+        history.restore(40, source.blocks().copied());
+
+        let blocks = history
+            .blocks()
+            .map(|block| (block.start, block.count))
+            .collect::<heapless::Vec<_, 2>>();
+        assert_eq!(blocks.as_slice(), &[(spaced(2), 2), (spaced(4), 2)]);
+        assert_eq!(history.first_sequence(), 41);
+        assert_eq!(history.last_sequence(), Some(42));
+
+        // The newest block is still open
+        history.push(spaced(6), true);
+        assert_eq!(history.len(), 2);
+    }
+
+    #[test]
+    fn test_push_reports_evicted_block() {
+        let mut history: History<1> = History::new();
+
+        for i in 0..4 {
+            assert_eq!(history.push(spaced(i), true), None);
+        }
+
+        let evicted = history.push(spaced(4), true);
+        assert_eq!(
+            evicted.map(|block| (block.start, block.count)),
+            Some((0, 2))
+        );
+    }
+}
@@ -1,5 +1,29 @@
-#![no_std]
+#![cfg_attr(not(feature = "std"), no_std)]
 
+pub mod alerts;
+pub mod bonds;
+pub mod clock;
+mod crc;
+pub mod decay;
+#[cfg(feature = "std")]
+pub mod decode;
+pub mod episodes;
+mod error;
+pub mod export;
+pub mod histogram;
+pub mod history;
+#[cfg(any(test, feature = "std"))]
+pub mod mock;
+pub mod occupancy;
+pub mod persist;
+#[cfg(feature = "replay")]
+pub mod replay;
+pub mod report;
+pub mod rollup;
+pub mod statistics;
 pub mod storage;
+pub mod updates;
+pub mod windows;
 
+pub use error::Error;
 pub use storage::{Events, RecordType};
@@ -0,0 +1,109 @@
+//! RAM-backed NOR flash for exercising [`crate::persist`] on the host.
+
+use embedded_storage::nor_flash::{
+    ErrorType, NorFlash, NorFlashErrorKind, ReadNorFlash, check_erase, check_read, check_write,
+};
+
+const MAX_SECTORS: usize = 64;
+
+/// NOR flash of `SIZE` bytes with 4KiB sectors and 4-byte words, like the
+/// ESP32 SPI flash.
+///
+/// Writes can only clear bits, as on real NOR flash. A write budget can be
+/// set to simulate power loss part way through an operation.
+pub struct MockFlash<const SIZE: usize> {
+    data: [u8; SIZE],
+    erases: [u32; MAX_SECTORS],
+    write_budget: Option<usize>,
+}
+
+impl<const SIZE: usize> Default for MockFlash<SIZE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const SIZE: usize> MockFlash<SIZE> {
+    pub const fn new() -> Self {
+        assert!(SIZE.is_multiple_of(Self::ERASE_SIZE) && SIZE / Self::ERASE_SIZE <= MAX_SECTORS);
+
+        Self {
+            data: [0xff; SIZE],
+            erases: [0; MAX_SECTORS],
+            write_budget: None,
+        }
+    }
+
+    /// Lets only the next `bytes` bytes be written; everything after fails as
+    /// if power was lost. `None` removes the limit.
+    pub fn set_write_budget(&mut self, bytes: Option<usize>) {
+        self.write_budget = bytes;
+    }
+
+    /// Number of times each sector has been erased.
+    pub fn erase_counts(&self) -> &[u32] {
+        &self.erases[..SIZE / Self::ERASE_SIZE]
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn as_bytes_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+}
+
+impl<const SIZE: usize> ErrorType for MockFlash<SIZE> {
+    type Error = NorFlashErrorKind;
+}
+
+impl<const SIZE: usize> ReadNorFlash for MockFlash<SIZE> {
+    const READ_SIZE: usize = 4;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        check_read(self, offset, bytes.len())?;
+
+        let offset = offset as usize;
+        bytes.copy_from_slice(&self.data[offset..offset + bytes.len()]);
+
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        SIZE
+    }
+}
+
+impl<const SIZE: usize> NorFlash for MockFlash<SIZE> {
+    const WRITE_SIZE: usize = 4;
+    const ERASE_SIZE: usize = 4096;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        check_erase(self, from, to)?;
+
+        for sector in (from as usize..to as usize).step_by(Self::ERASE_SIZE) {
+            self.data[sector..sector + Self::ERASE_SIZE].fill(0xff);
+            self.erases[sector / Self::ERASE_SIZE] += 1;
+        }
+
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        check_write(self, offset, bytes.len())?;
+
+        let offset = offset as usize;
+        for (index, byte) in bytes.iter().enumerate() {
+            if let Some(budget) = self.write_budget.as_mut() {
+                if *budget == 0 {
+                    return Err(NorFlashErrorKind::Other);
+                }
+                *budget -= 1;
+            }
+            self.data[offset + index] &= byte;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,244 @@
+//! Idle, Active and Busy states derived from a window's duty cycle.
+//!
+//! The share of High samples in one window picks the state a channel should
+//! be in. The channel only moves there once it has spent the current state's
+//! minimum dwell time where it is, so a brief burst or lull cannot make the
+//! state flicker.
+
+use crate::windows::Windows;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum State {
+    Idle = 0,
+    Active = 1,
+    Busy = 2,
+}
+
+impl State {
+    pub const COUNT: usize = 3;
+
+    /// Single byte identifying the state, for advertising.
+    pub fn as_byte(self) -> u8 {
+        self as u8
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Classifier {
+    /// Window whose duty cycle is classified.
+    pub bucket: usize,
+    /// Duty cycle, in percent, at which the channel is at least Active.
+    pub active_at: u8,
+    /// Duty cycle, in percent, at which the channel is Busy.
+    pub busy_at: u8,
+    /// Time in milliseconds the channel must spend in each state, indexed by
+    /// [`State::as_byte`], before it can leave it.
+    pub min_dwell_ms: [u64; State::COUNT],
+}
+
+impl Classifier {
+    /// The state a window with `duty_cycle` percent High samples calls for.
+    pub fn target(&self, duty_cycle: u8) -> State {
+        if duty_cycle >= self.busy_at {
+            State::Busy
+        } else if duty_cycle >= self.active_at {
+            State::Active
+        } else {
+            State::Idle
+        }
+    }
+}
+
+/// A channel's state and when it was entered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Classification {
+    pub state: State,
+    /// Time in milliseconds since the channel entered `state`.
+    pub elapsed_ms: u64,
+}
+
+/// A classifier and the state it has settled on.
+pub(crate) struct Occupancy {
+    classifier: Classifier,
+    /// Current state and the timestamp it was entered at, once a sample has
+    /// been classified.
+    current: Option<(State, u64)>,
+}
+
+impl Occupancy {
+    pub(crate) fn new(classifier: Classifier) -> Self {
+        Self {
+            classifier,
+            current: None,
+        }
+    }
+
+    /// Re-evaluates the state after a sample at `now` and returns the new
+    /// state if it changed.
+    pub(crate) fn update<const WINDOWS: usize>(
+        &mut self,
+        windows: &Windows<WINDOWS>,
+        now: u64,
+    ) -> Option<State> {
+        let duty_cycle = windows
+            .duty_cycle()
+            .get(self.classifier.bucket)
+            .copied()
+            .unwrap_or(0);
+        let target = self.classifier.target(duty_cycle);
+
+        let Some((state, since)) = self.current else {
+            self.current = Some((target, now));
+            return None;
+        };
+        if target == state
+            || now.saturating_sub(since) < self.classifier.min_dwell_ms[state as usize]
+        {
+            return None;
+        }
+
+        self.current = Some((target, now));
+        Some(target)
+    }
+
+    /// Starts over from the windows as they are at `now`, after the history
+    /// has been replaced wholesale.
+    pub(crate) fn rebuild<const WINDOWS: usize>(&mut self, windows: &Windows<WINDOWS>, now: u64) {
+        self.current = None;
+        self.update(windows, now);
+    }
+
+    pub(crate) fn classification(&self, now: u64) -> Option<Classification> {
+        let (state, since) = self.current?;
+        Some(Classification {
+            state,
+            elapsed_ms: now.saturating_sub(since),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::History;
+
+    const CLASSIFIER: Classifier = Classifier {
+        bucket: 0,
+        active_at: 20,
+        busy_at: 70,
+        min_dwell_ms: [500, 2000, 1000],
+    };
+
+    struct Harness {
+        history: History<64>,
+        windows: Windows<1>,
+        occupancy: Occupancy,
+        now: u64,
+    }
+
+    impl Harness {
+        fn new() -> Self {
+            Self {
+                history: History::new(),
+                windows: Windows::new([1000]),
+                occupancy: Occupancy::new(CLASSIFIER),
+                now: 0,
+            }
+        }
+
+        /// Records one sample every 100ms for `duration` milliseconds and
+        /// returns every transition with its timestamp.
+        fn run(&mut self, duration: u64, high: bool) -> heapless::Vec<(u64, State), 8> {
+            let mut transitions = heapless::Vec::new();
+            for _ in 0..duration / 100 {
+                let evicted = self.history.push(self.now, high);
+                self.windows.record(&self.history, evicted, self.now, high);
+                if let Some(state) = self.occupancy.update(&self.windows, self.now) {
+                    transitions.push((self.now, state)).unwrap();
+                }
+                self.now += 100;
+            }
+            transitions
+        }
+
+        fn state(&self) -> State {
+            self.occupancy.classification(self.now).unwrap().state
+        }
+    }
+
+    #[test]
+    fn test_thresholds_pick_target() {
+        assert_eq!(CLASSIFIER.target(0), State::Idle);
+        assert_eq!(CLASSIFIER.target(19), State::Idle);
+        assert_eq!(CLASSIFIER.target(20), State::Active);
+        assert_eq!(CLASSIFIER.target(69), State::Active);
+        assert_eq!(CLASSIFIER.target(70), State::Busy);
+        assert_eq!(CLASSIFIER.target(100), State::Busy);
+    }
+
+    #[test]
+    fn test_first_sample_sets_state_without_transition() {
+        let mut harness = Harness::new();
+        assert_eq!(harness.occupancy.classification(0), None);
+
+        assert!(harness.run(100, true).is_empty());
+        assert_eq!(
+            harness.occupancy.classification(400),
+            Some(Classification {
+                state: State::Busy,
+                elapsed_ms: 400,
+            })
+        );
+    }
+
+    #[test]
+    fn test_transitions_follow_duty_cycle() {
+        let mut harness = Harness::new();
+        assert!(harness.run(1000, false).is_empty());
+        assert_eq!(harness.state(), State::Idle);
+
+        // Two High samples in the last ten make 20%
+        assert_eq!(harness.run(200, true).as_slice(), &[(1100, State::Active)]);
+        // Seven make 70%, but Active has to last two seconds first
+        assert!(harness.run(500, true).is_empty());
+        assert_eq!(harness.run(1500, true).as_slice(), &[(3100, State::Busy)]);
+
+        // The window has emptied by the time Busy may be left, so the channel
+        // drops straight to Idle
+        assert_eq!(harness.run(2000, false).as_slice(), &[(4100, State::Idle)]);
+        assert_eq!(
+            harness.occupancy.classification(4600).unwrap().elapsed_ms,
+            500
+        );
+    }
+
+    #[test]
+    fn test_dwell_outlasts_short_bursts() {
+        let mut harness = Harness::new();
+        harness.run(1000, false);
+
+        // The burst leaves the window a second later, but Active holds for
+        // its full two seconds
+        assert_eq!(harness.run(200, true).as_slice(), &[(1100, State::Active)]);
+        assert!(harness.run(1900, false).is_empty());
+        assert_eq!(harness.state(), State::Active);
+        assert_eq!(harness.run(100, false).as_slice(), &[(3100, State::Idle)]);
+    }
+
+    #[test]
+    fn test_rebuild_starts_a_new_dwell() {
+        let mut harness = Harness::new();
+        harness.run(1000, true);
+        assert_eq!(harness.state(), State::Busy);
+
+        harness.occupancy.rebuild(&harness.windows, 5000);
+        assert_eq!(
+            harness.occupancy.classification(5000),
+            Some(Classification {
+                state: State::Busy,
+                elapsed_ms: 0,
+            })
+        );
+    }
+}
@@ -0,0 +1,368 @@
+//! Wear-levelled, power-loss-safe storage of byte snapshots in NOR flash.
+//!
+//! The flash region is split into equally sized slots of whole erase sectors.
+//! Each save goes to the slot after the newest one, so erases rotate evenly
+//! across the region, and the newest valid slot is never touched while a new
+//! snapshot is written. A slot is laid out as:
+//!
+//! | offset | size | field                                              |
+//! |--------|------|----------------------------------------------------|
+//! | 0      | 4    | magic, [`MAGIC`]                                   |
+//! | 4      | 4    | sequence number, higher is newer                   |
+//! | 8      | 4    | payload length                                     |
+//! | 12     | 2    | CRC-16/CCITT-FALSE of sequence, length and payload |
+//! | 14     | 18   | padding                                            |
+//! | 32     | ...  | payload                                            |
+//!
+//! The header is written after the payload, so a slot interrupted by power
+//! loss either has no magic or fails its CRC and is skipped when loading.
+//! All fields are big-endian.
+
+use embedded_storage::nor_flash::NorFlash;
+
+use crate::crc::{crc16, crc16_update};
+
+pub const MAGIC: u32 = 0x4556_5331;
+
+/// Reads and writes go through a buffer of this size, which must be a
+/// multiple of the flash read and write sizes.
+const CHUNK_LEN: usize = 32;
+const HEADER_LEN: usize = CHUNK_LEN;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PersistError<E> {
+    Flash(E),
+    /// The region is not made of at least two whole slots of erase sectors,
+    /// or the flash cannot be accessed in `CHUNK_LEN` pieces.
+    Layout,
+    /// The payload does not fit in a slot, or the stored payload does not fit
+    /// in the buffer it is being loaded into.
+    TooLarge {
+        max: usize,
+        actual: usize,
+    },
+}
+
+impl<E> From<E> for PersistError<E> {
+    fn from(error: E) -> Self {
+        PersistError::Flash(error)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Newest {
+    slot: u32,
+    sequence: u32,
+    len: usize,
+}
+
+pub struct FlashStore<F> {
+    flash: F,
+    start: u32,
+    slot_size: u32,
+    slots: u32,
+    newest: Option<Newest>,
+    scanned: bool,
+}
+
+impl<F: NorFlash> FlashStore<F> {
+    /// Uses `slots` slots of `slot_size` bytes each, starting at `start`.
+    pub fn new(
+        flash: F,
+        start: u32,
+        slot_size: u32,
+        slots: u32,
+    ) -> Result<Self, PersistError<F::Error>> {
+        let erase_size = F::ERASE_SIZE as u32;
+        let layout_is_valid = slots >= 2
+            && start.is_multiple_of(erase_size)
+            && slot_size.is_multiple_of(erase_size)
+            && slot_size as usize > HEADER_LEN
+            && CHUNK_LEN.is_multiple_of(F::WRITE_SIZE)
+            && CHUNK_LEN.is_multiple_of(F::READ_SIZE)
+            && slot_size
+                .checked_mul(slots)
+                .and_then(|len| len.checked_add(start))
+                .is_some_and(|end| end as usize <= flash.capacity());
+        if !layout_is_valid {
+            return Err(PersistError::Layout);
+        }
+
+        Ok(Self {
+            flash,
+            start,
+            slot_size,
+            slots,
+            newest: None,
+            scanned: false,
+        })
+    }
+
+    /// Largest payload a slot can hold.
+    pub fn max_payload_len(&self) -> usize {
+        self.slot_size as usize - HEADER_LEN
+    }
+
+    pub fn into_inner(self) -> F {
+        self.flash
+    }
+
+    /// Copies the newest valid snapshot into `bytes` and returns its length,
+    /// or `None` if the region holds no valid snapshot.
+    pub fn load(&mut self, bytes: &mut [u8]) -> Result<Option<usize>, PersistError<F::Error>> {
+        self.scan()?;
+
+        let Some(newest) = self.newest else {
+            return Ok(None);
+        };
+        if newest.len > bytes.len() {
+            return Err(PersistError::TooLarge {
+                max: bytes.len(),
+                actual: newest.len,
+            });
+        }
+
+        let mut chunk = [0; CHUNK_LEN];
+        let payload_start = self.slot_start(newest.slot) + HEADER_LEN as u32;
+        for (index, piece) in bytes[..newest.len].chunks_mut(CHUNK_LEN).enumerate() {
+            self.flash
+                .read(payload_start + (index * CHUNK_LEN) as u32, &mut chunk)?;
+            piece.copy_from_slice(&chunk[..piece.len()]);
+        }
+
+        Ok(Some(newest.len))
+    }
+
+    /// Writes `payload` as the newest snapshot.
+    pub fn save(&mut self, payload: &[u8]) -> Result<(), PersistError<F::Error>> {
+        if payload.len() > self.max_payload_len() {
+            return Err(PersistError::TooLarge {
+                max: self.max_payload_len(),
+                actual: payload.len(),
+            });
+        }
+        self.scan()?;
+
+        let (slot, sequence) = match self.newest {
+            Some(newest) => (
+                (newest.slot + 1) % self.slots,
+                newest.sequence.wrapping_add(1),
+            ),
+            None => (0, 0),
+        };
+        let slot_start = self.slot_start(slot);
+        self.flash.erase(slot_start, slot_start + self.slot_size)?;
+
+        let mut chunk = [0xff; CHUNK_LEN];
+        for (index, piece) in payload.chunks(CHUNK_LEN).enumerate() {
+            chunk.fill(0xff);
+            chunk[..piece.len()].copy_from_slice(piece);
+            self.flash
+                .write(slot_start + (HEADER_LEN + index * CHUNK_LEN) as u32, &chunk)?;
+        }
+
+        let crc = crc16_update(crc16(&header_fields(sequence, payload.len())), payload);
+        chunk.fill(0xff);
+        chunk[0..4].copy_from_slice(&MAGIC.to_be_bytes());
+        chunk[4..12].copy_from_slice(&header_fields(sequence, payload.len()));
+        chunk[12..14].copy_from_slice(&crc.to_be_bytes());
+        self.flash.write(slot_start, &chunk)?;
+
+        self.newest = Some(Newest {
+            slot,
+            sequence,
+            len: payload.len(),
+        });
+
+        Ok(())
+    }
+
+    fn slot_start(&self, slot: u32) -> u32 {
+        self.start + slot * self.slot_size
+    }
+
+    fn scan(&mut self) -> Result<(), PersistError<F::Error>> {
+        if self.scanned {
+            return Ok(());
+        }
+
+        let mut newest: Option<Newest> = None;
+        for slot in 0..self.slots {
+            let Some((sequence, len)) = self.validate(slot)? else {
+                continue;
+            };
+            if newest.is_none_or(|newest| sequence > newest.sequence) {
+                newest = Some(Newest {
+                    slot,
+                    sequence,
+                    len,
+                });
+            }
+        }
+
+        self.newest = newest;
+        self.scanned = true;
+
+        Ok(())
+    }
+
+    /// Returns the sequence number and payload length of a slot that holds a
+    /// complete snapshot.
+    fn validate(&mut self, slot: u32) -> Result<Option<(u32, usize)>, PersistError<F::Error>> {
+        let slot_start = self.slot_start(slot);
+        let mut chunk = [0; CHUNK_LEN];
+        self.flash.read(slot_start, &mut chunk)?;
+
+        let magic = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        let sequence = u32::from_be_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]);
+        let len = u32::from_be_bytes([chunk[8], chunk[9], chunk[10], chunk[11]]) as usize;
+        let expected = u16::from_be_bytes([chunk[12], chunk[13]]);
+        if magic != MAGIC || len > self.max_payload_len() {
+            return Ok(None);
+        }
+
+        let mut crc = crc16(&chunk[4..12]);
+        let mut remaining = len;
+        let mut offset = slot_start + HEADER_LEN as u32;
+        while remaining > 0 {
+            self.flash.read(offset, &mut chunk)?;
+            let piece = remaining.min(CHUNK_LEN);
+            crc = crc16_update(crc, &chunk[..piece]);
+            remaining -= piece;
+            offset += CHUNK_LEN as u32;
+        }
+
+        Ok((crc == expected).then_some((sequence, len)))
+    }
+}
+
+fn header_fields(sequence: u32, len: usize) -> [u8; 8] {
+    let mut fields = [0; 8];
+    fields[..4].copy_from_slice(&sequence.to_be_bytes());
+    fields[4..].copy_from_slice(&(len as u32).to_be_bytes());
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockFlash;
+
+    const SECTOR: u32 = 4096;
+
+    #[test]
+    fn test_empty_region_loads_nothing() {
+        let mut flash: MockFlash<{ 4 * 4096 }> = MockFlash::new();
+        let mut store = FlashStore::new(&mut flash, 0, SECTOR, 4).unwrap();
+
+        assert_eq!(store.load(&mut [0; 64]), Ok(None));
+    }
+
+    #[test]
+    fn test_newest_snapshot_survives_reload() {
+        let mut flash: MockFlash<{ 4 * 4096 }> = MockFlash::new();
+
+        let mut store = FlashStore::new(&mut flash, 0, SECTOR, 3).unwrap();
+        store.save(b"first").unwrap();
+        store
+            .save(b"second snapshot, longer than a single chunk of flash")
+            .unwrap();
+
+        let mut store = FlashStore::new(&mut flash, 0, SECTOR, 3).unwrap();
+        let mut bytes = [0; 128];
+        let len = store.load(&mut bytes).unwrap().unwrap();
+        assert_eq!(
+            &bytes[..len],
+            b"second snapshot, longer than a single chunk of flash"
+        );
+    }
+
+    #[test]
+    fn test_saves_rotate_across_slots() {
+        let mut flash: MockFlash<{ 4 * 4096 }> = MockFlash::new();
+
+        let mut store = FlashStore::new(&mut flash, SECTOR, SECTOR, 3).unwrap();
+        for index in 0..9u8 {
+            store.save(&[index; 10]).unwrap();
+        }
+
+        assert_eq!(flash.erase_counts(), &[0, 3, 3, 3]);
+
+        let mut store = FlashStore::new(&mut flash, SECTOR, SECTOR, 3).unwrap();
+        let mut bytes = [0; 10];
+        assert_eq!(store.load(&mut bytes), Ok(Some(10)));
+        assert_eq!(bytes, [8; 10]);
+    }
+
+    #[test]
+    fn test_interrupted_save_keeps_previous_snapshot() {
+        let mut flash: MockFlash<{ 2 * 4096 }> = MockFlash::new();
+
+        let mut store = FlashStore::new(&mut flash, 0, SECTOR, 2).unwrap();
+        store.save(&[1; 100]).unwrap();
+
+        // Lose power at every point of the next save before its CRC is
+        // complete, which is after four payload chunks and 14 header bytes
+        for budget in 0..(4 * CHUNK_LEN + 14) {
+            let mut store = FlashStore::new(&mut flash, 0, SECTOR, 2).unwrap();
+            store.flash.set_write_budget(Some(budget));
+            assert!(store.save(&[2; 100]).is_err());
+            store.flash.set_write_budget(None);
+
+            let mut store = FlashStore::new(&mut flash, 0, SECTOR, 2).unwrap();
+            let mut bytes = [0; 100];
+            assert_eq!(store.load(&mut bytes), Ok(Some(100)));
+            assert_eq!(bytes, [1; 100]);
+        }
+    }
+
+    #[test]
+    fn test_corrupt_slot_is_skipped() {
+        let mut flash: MockFlash<{ 2 * 4096 }> = MockFlash::new();
+
+        let mut store = FlashStore::new(&mut flash, 0, SECTOR, 2).unwrap();
+        store.save(b"older").unwrap();
+        store.save(b"newer").unwrap();
+
+        // Flip a payload bit of the newer snapshot in the second slot
+        flash.as_bytes_mut()[4096 + HEADER_LEN] ^= 0x01;
+
+        let mut store = FlashStore::new(&mut flash, 0, SECTOR, 2).unwrap();
+        let mut bytes = [0; 5];
+        assert_eq!(store.load(&mut bytes), Ok(Some(5)));
+        assert_eq!(&bytes, b"older");
+    }
+
+    #[test]
+    fn test_invalid_layouts_and_sizes_are_rejected() {
+        let mut flash: MockFlash<{ 2 * 4096 }> = MockFlash::new();
+
+        assert!(matches!(
+            FlashStore::new(&mut flash, 0, SECTOR, 1),
+            Err(PersistError::Layout)
+        ));
+        assert!(matches!(
+            FlashStore::new(&mut flash, 0, 1000, 2),
+            Err(PersistError::Layout)
+        ));
+        assert!(matches!(
+            FlashStore::new(&mut flash, SECTOR, SECTOR, 2),
+            Err(PersistError::Layout)
+        ));
+
+        let mut store = FlashStore::new(&mut flash, 0, SECTOR, 2).unwrap();
+        assert_eq!(
+            store.save(&[0; 4096]),
+            Err(PersistError::TooLarge {
+                max: 4096 - HEADER_LEN,
+                actual: 4096
+            })
+        );
+
+        store.save(&[0; 10]).unwrap();
+        assert_eq!(
+            store.load(&mut [0; 4]),
+            Err(PersistError::TooLarge { max: 4, actual: 10 })
+        );
+    }
+}
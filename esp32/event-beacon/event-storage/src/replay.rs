@@ -0,0 +1,293 @@
+//! Host-side replay of recorded input traces through [`Events`].
+//!
+//! A trace is CSV with one sample per line, `timestamp_ms,level[,channel]`.
+//! The level is `1`/`0`, `high`/`low` or `true`/`false`, and the channel
+//! defaults to 0. Blank lines, lines starting with `#` and a header line
+//! starting with `timestamp` are skipped. Timestamps must not go backwards.
+//!
+//! Samples are recorded through [`Events::record_channel`], with the mock
+//! embassy-time driver advanced to each timestamp, so the replay takes the
+//! same path as samples recorded on the device.
+
+use std::{fmt, sync::Mutex, vec::Vec};
+
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_time::{Duration, Instant, MockDriver};
+
+use crate::{Error, Events, RecordType};
+
+/// The mock driver is global, so only one replay can drive it at a time.
+static DRIVER: Mutex<()> = Mutex::new(());
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Sample {
+    pub timestamp_ms: u64,
+    pub channel: usize,
+    pub record_type: RecordType,
+}
+
+/// A channel's report at one point in the replay.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Frame<const WINDOWS: usize> {
+    pub timestamp_ms: u64,
+    pub channel: usize,
+    pub counts: [u16; WINDOWS],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReplayError {
+    /// The line, counting from 1, is not a valid sample.
+    Syntax { line: usize },
+    /// The line, counting from 1, has an earlier timestamp than the one
+    /// before it.
+    OutOfOrder { line: usize },
+    /// `Events` rejected a sample, for instance for an unknown channel.
+    Events(Error),
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::Syntax { line } => write!(f, "invalid sample on line={line}"),
+            ReplayError::OutOfOrder { line } => {
+                write!(f, "timestamp goes backwards on line={line}")
+            }
+            ReplayError::Events(error) => write!(f, "could not record sample error={error:?}"),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+impl From<Error> for ReplayError {
+    fn from(error: Error) -> Self {
+        ReplayError::Events(error)
+    }
+}
+
+pub fn parse_trace(trace: &str) -> Result<Vec<Sample>, ReplayError> {
+    let mut samples: Vec<Sample> = Vec::new();
+
+    for (index, line) in trace.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with("timestamp") {
+            continue;
+        }
+
+        let sample = parse_sample(line).ok_or(ReplayError::Syntax { line: line_number })?;
+        if samples
+            .last()
+            .is_some_and(|last| sample.timestamp_ms < last.timestamp_ms)
+        {
+            return Err(ReplayError::OutOfOrder { line: line_number });
+        }
+        samples.push(sample);
+    }
+
+    Ok(samples)
+}
+
+fn parse_sample(line: &str) -> Option<Sample> {
+    let mut fields = line.split(',').map(str::trim);
+    let timestamp_ms = fields.next()?.parse().ok()?;
+    let record_type = match fields.next()?.to_ascii_lowercase().as_str() {
+        "1" | "high" | "true" => RecordType::High,
+        "0" | "low" | "false" => RecordType::Low,
+        _ => return None,
+    };
+    let channel = match fields.next() {
+        Some(channel) => channel.parse().ok()?,
+        None => 0,
+    };
+    if fields.next().is_some() {
+        return None;
+    }
+
+    Some(Sample {
+        timestamp_ms,
+        channel,
+        record_type,
+    })
+}
+
+/// Records `samples` into `events` and passes the report timeline to
+/// `frame`.
+///
+/// With `every_ms`, every channel is reported each time the replay crosses a
+/// multiple of `every_ms`, as a device advertising on that interval would,
+/// and once more after the last sample. Without it, the channel a sample was
+/// recorded on is reported after each sample.
+pub fn replay<
+    const WINDOWS: usize,
+    const CAPACITY: usize,
+    const CHANNELS: usize,
+    M: RawMutex,
+    const SCORES: usize,
+>(
+    events: &Events<WINDOWS, CAPACITY, CHANNELS, M, SCORES>,
+    samples: &[Sample],
+    every_ms: Option<u64>,
+    mut frame: impl FnMut(Frame<WINDOWS>),
+) -> Result<(), ReplayError> {
+    let _driver = DRIVER
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let driver = MockDriver::get();
+    driver.reset();
+
+    embassy_futures::block_on(async {
+        let every_ms = every_ms.filter(|&every_ms| every_ms > 0);
+        let mut next_report = every_ms;
+
+        for sample in samples {
+            if let (Some(every_ms), Some(next)) = (every_ms, next_report.as_mut()) {
+                while *next <= sample.timestamp_ms {
+                    report_channels(events, *next, &mut frame).await?;
+                    *next += every_ms;
+                }
+            }
+
+            let elapsed = sample.timestamp_ms - Instant::now().as_millis();
+            driver.advance(Duration::from_millis(elapsed));
+            events
+                .record_channel(sample.channel, sample.record_type)
+                .await?;
+
+            if every_ms.is_none() {
+                frame(Frame {
+                    timestamp_ms: sample.timestamp_ms,
+                    channel: sample.channel,
+                    counts: events.channel_report(sample.channel).await?,
+                });
+            }
+        }
+
+        if let (Some(_), Some(last)) = (every_ms, samples.last()) {
+            report_channels(events, last.timestamp_ms, &mut frame).await?;
+        }
+        Ok(())
+    })
+}
+
+async fn report_channels<
+    const WINDOWS: usize,
+    const CAPACITY: usize,
+    const CHANNELS: usize,
+    M: RawMutex,
+    const SCORES: usize,
+>(
+    events: &Events<WINDOWS, CAPACITY, CHANNELS, M, SCORES>,
+    timestamp_ms: u64,
+    frame: &mut impl FnMut(Frame<WINDOWS>),
+) -> Result<(), ReplayError> {
+    for channel in 0..CHANNELS {
+        frame(Frame {
+            timestamp_ms,
+            channel,
+            counts: events.channel_report(channel).await?,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+    #[test]
+    fn test_parse_trace() {
+        let trace = "timestamp_ms,level,channel\n\
+                     # warm-up\n\
+                     0,low\n\
+                     \n\
+                     100, 1, 1\n\
+                     100,HIGH\n";
+
+        assert_eq!(
+            parse_trace(trace),
+            Ok(std::vec![
+                Sample {
+                    timestamp_ms: 0,
+                    channel: 0,
+                    record_type: RecordType::Low,
+                },
+                Sample {
+                    timestamp_ms: 100,
+                    channel: 1,
+                    record_type: RecordType::High,
+                },
+                Sample {
+                    timestamp_ms: 100,
+                    channel: 0,
+                    record_type: RecordType::High,
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_trace_rejects_bad_lines() {
+        assert_eq!(
+            parse_trace("0,1\n100,maybe\n"),
+            Err(ReplayError::Syntax { line: 2 })
+        );
+        assert_eq!(
+            parse_trace("0,1,0,0\n"),
+            Err(ReplayError::Syntax { line: 1 })
+        );
+        assert_eq!(
+            parse_trace("200,1\n100,0\n"),
+            Err(ReplayError::OutOfOrder { line: 2 })
+        );
+    }
+
+    #[test]
+    fn test_replay_reports_timeline() {
+        let events: Events<2, 16, 2, NoopRawMutex> = Events::with_channels(["a", "b"], [250, 1000]);
+        let samples = parse_trace("0,1\n100,1\n200,0,1\n300,1\n1200,0\n").unwrap();
+
+        let mut frames = Vec::new();
+        replay(&events, &samples, Some(500), |frame| frames.push(frame)).unwrap();
+
+        let frame = |timestamp_ms, channel, counts| Frame {
+            timestamp_ms,
+            channel,
+            counts,
+        };
+        assert_eq!(
+            frames,
+            std::vec![
+                frame(500, 0, [2, 3]),
+                frame(500, 1, [0, 0]),
+                frame(1000, 0, [2, 3]),
+                frame(1000, 1, [0, 0]),
+                frame(1200, 0, [0, 1]),
+                frame(1200, 1, [0, 0]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_replay_reports_each_sample() {
+        let events: Events<1, 16, 2, NoopRawMutex> = Events::with_channels(["a", "b"], [1000]);
+        let samples = parse_trace("0,1\n100,1,1\n2000,1\n").unwrap();
+
+        let mut frames = Vec::new();
+        replay(&events, &samples, None, |frame| frames.push(frame)).unwrap();
+        assert_eq!(
+            frames
+                .iter()
+                .map(|frame| (frame.timestamp_ms, frame.channel, frame.counts))
+                .collect::<Vec<_>>(),
+            std::vec![(0, 0, [1]), (100, 1, [1]), (2000, 0, [1])]
+        );
+
+        let samples = parse_trace("0,1,2\n").unwrap();
+        assert_eq!(
+            replay(&events, &samples, None, |_| {}),
+            Err(ReplayError::Events(Error::UnknownChannel(2)))
+        );
+    }
+}
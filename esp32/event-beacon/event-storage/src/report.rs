@@ -0,0 +1,330 @@
+//! Versioned, self-describing report encoding.
+//!
+//! Every report starts with a fixed header, followed by the sections named in
+//! the format byte and a CRC. All multi-byte fields are big-endian.
+//!
+//! | offset | size    | field                                          |
+//! |--------|---------|------------------------------------------------|
+//! | 0      | 1       | version, currently [`VERSION`]                 |
+//! | 1      | 1       | format, a bitmask of the sections that follow  |
+//! | 2      | 2       | device id                                      |
+//! | 4      | 2       | sequence number                                |
+//! | 6      | 1       | channel index                                  |
+//! | 7      | 1       | bucket count `n`                               |
+//! | 8      | `4 * n` | window lengths in milliseconds                 |
+//! | ...    | ...     | sections, in the order of their format bits    |
+//! | ...    | 2       | CRC-16/CCITT-FALSE of every preceding byte     |
+//!
+//! Sections:
+//!
+//! - [`FORMAT_BUCKETS`]: `n` High sample counts, `u16` each.
+//! - [`FORMAT_ACTIVITY`]: for each window, the number of activations as a
+//!   `u16` followed by the longest episode in milliseconds as a `u32`.
+//! - [`FORMAT_DUTY_CYCLE`]: `n` percentages of High samples, `u8` each.
+//! - [`FORMAT_STREAKS`]: the longest High episode, the time since the last
+//!   High sample and the time since the last Low sample, in milliseconds as
+//!   `u32` each. [`NEVER`] stands for no such sample.
+//! - [`FORMAT_COVERAGE`]: `n` spans in milliseconds, `u32` each, of each
+//!   window that recorded history actually covers. A span shorter than the
+//!   window length means the counts describe less time than the window
+//!   claims, for instance right after boot.
+
+use crate::{Error, crc::crc16, episodes::Activity, statistics::Streaks};
+
+pub const VERSION: u8 = 1;
+
+pub const FORMAT_BUCKETS: u8 = 0b0000_0001;
+pub const FORMAT_ACTIVITY: u8 = 0b0000_0010;
+pub const FORMAT_DUTY_CYCLE: u8 = 0b0000_0100;
+pub const FORMAT_STREAKS: u8 = 0b0000_1000;
+pub const FORMAT_COVERAGE: u8 = 0b0001_0000;
+
+/// Recency value for a level that has not been seen in history.
+pub const NEVER: u32 = u32::MAX;
+
+pub(crate) const STREAKS_LEN: usize = 12;
+
+pub(crate) const HEADER_LEN: usize = 8;
+pub(crate) const CRC_LEN: usize = 2;
+
+/// A single channel's report, ready to be encoded.
+pub struct Report<'a> {
+    pub device_id: u16,
+    pub sequence: u16,
+    pub channel: u8,
+    pub breakpoints: &'a [u64],
+    pub buckets: &'a [u16],
+    /// Activations and episode lengths, included when present.
+    pub activity: Option<&'a [Activity]>,
+    /// Duty cycle percentages, included when present.
+    pub duty_cycle: Option<&'a [u8]>,
+    /// Streak and recency figures, included when present.
+    pub streaks: Option<Streaks>,
+    /// Time covered by each window, included when present.
+    pub coverage_ms: Option<&'a [u32]>,
+}
+
+impl Report<'_> {
+    pub fn format(&self) -> u8 {
+        let mut format = FORMAT_BUCKETS;
+        if self.activity.is_some() {
+            format |= FORMAT_ACTIVITY;
+        }
+        if self.duty_cycle.is_some() {
+            format |= FORMAT_DUTY_CYCLE;
+        }
+        if self.streaks.is_some() {
+            format |= FORMAT_STREAKS;
+        }
+        if self.coverage_ms.is_some() {
+            format |= FORMAT_COVERAGE;
+        }
+        format
+    }
+
+    pub fn encoded_len(&self) -> usize {
+        let windows = self.breakpoints.len();
+        let mut len = HEADER_LEN + windows * 4 + windows * 2 + CRC_LEN;
+        if self.activity.is_some() {
+            len += windows * 6;
+        }
+        if self.duty_cycle.is_some() {
+            len += windows;
+        }
+        if self.streaks.is_some() {
+            len += STREAKS_LEN;
+        }
+        if self.coverage_ms.is_some() {
+            len += windows * 4;
+        }
+        len
+    }
+
+    /// Encodes the report into `bytes` and returns the number of bytes written.
+    ///
+    /// Every per-window section must hold exactly one entry per breakpoint.
+    pub fn encode(&self, bytes: &mut [u8]) -> Result<usize, Error> {
+        let windows = self.breakpoints.len();
+        let count = u8::try_from(windows).map_err(|_| Error::TooManyWindows(windows))?;
+        let sections = [
+            Some(self.buckets.len()),
+            self.activity.map(<[_]>::len),
+            self.duty_cycle.map(<[_]>::len),
+            self.coverage_ms.map(<[_]>::len),
+        ];
+        if let Some(actual) = sections.into_iter().flatten().find(|&len| len != windows) {
+            return Err(Error::SectionLength {
+                expected: windows,
+                actual,
+            });
+        }
+        let expected = self.encoded_len();
+        if bytes.len() < expected {
+            return Err(Error::BufferLength {
+                expected,
+                actual: bytes.len(),
+            });
+        }
+
+        bytes[0] = VERSION;
+        bytes[1] = self.format();
+        bytes[2..4].copy_from_slice(&self.device_id.to_be_bytes());
+        bytes[4..6].copy_from_slice(&self.sequence.to_be_bytes());
+        bytes[6] = self.channel;
+        bytes[7] = count;
+
+        let mut offset = HEADER_LEN;
+        for &breakpoint in self.breakpoints {
+            let breakpoint = u32::try_from(breakpoint).unwrap_or(u32::MAX);
+            bytes[offset..offset + 4].copy_from_slice(&breakpoint.to_be_bytes());
+            offset += 4;
+        }
+        for &bucket in self.buckets {
+            bytes[offset..offset + 2].copy_from_slice(&bucket.to_be_bytes());
+            offset += 2;
+        }
+        for activity in self.activity.iter().flat_map(|activity| activity.iter()) {
+            bytes[offset..offset + 2].copy_from_slice(&activity.activations.to_be_bytes());
+            bytes[offset + 2..offset + 6].copy_from_slice(&activity.longest_ms.to_be_bytes());
+            offset += 6;
+        }
+        for &duty_cycle in self
+            .duty_cycle
+            .iter()
+            .flat_map(|duty_cycle| duty_cycle.iter())
+        {
+            bytes[offset] = duty_cycle;
+            offset += 1;
+        }
+        if let Some(streaks) = self.streaks {
+            let fields = [
+                streaks.longest_high_ms,
+                streaks.since_high_ms.unwrap_or(NEVER),
+                streaks.since_low_ms.unwrap_or(NEVER),
+            ];
+            for field in fields {
+                bytes[offset..offset + 4].copy_from_slice(&field.to_be_bytes());
+                offset += 4;
+            }
+        }
+        for &coverage_ms in self
+            .coverage_ms
+            .iter()
+            .flat_map(|coverage_ms| coverage_ms.iter())
+        {
+            bytes[offset..offset + 4].copy_from_slice(&coverage_ms.to_be_bytes());
+            offset += 4;
+        }
+
+        let crc = crc16(&bytes[..offset]);
+        bytes[offset..offset + CRC_LEN].copy_from_slice(&crc.to_be_bytes());
+
+        Ok(offset + CRC_LEN)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_layout() {
+        let report = Report {
+            device_id: 0x0102,
+            sequence: 0x0304,
+            channel: 5,
+            breakpoints: &[1000, 600000],
+            buckets: &[7, 0x0809],
+            activity: None,
+            duty_cycle: None,
+            streaks: None,
+            coverage_ms: None,
+        };
+
+        let mut bytes = [0; 24];
+        assert_eq!(report.encode(&mut bytes), Ok(22));
+        assert_eq!(
+            &bytes[..20],
+            &[
+                1, 0b1, 1, 2, 3, 4, 5, 2, // header
+                0, 0, 0x03, 0xe8, 0, 0x09, 0x27, 0xc0, // windows
+                0, 7, 8, 9, // buckets
+            ]
+        );
+        assert_eq!(&bytes[20..22], &crc16(&bytes[..20]).to_be_bytes());
+    }
+
+    #[test]
+    fn test_encode_activity_section() {
+        let report = Report {
+            device_id: 0,
+            sequence: 0,
+            channel: 0,
+            breakpoints: &[1000],
+            buckets: &[3],
+            activity: Some(&[Activity {
+                activations: 2,
+                longest_ms: 0x0102_0304,
+            }]),
+            duty_cycle: None,
+            streaks: None,
+            coverage_ms: None,
+        };
+
+        let mut bytes = [0; 22];
+        assert_eq!(report.encode(&mut bytes), Ok(22));
+        assert_eq!(bytes[1], FORMAT_BUCKETS | FORMAT_ACTIVITY);
+        assert_eq!(&bytes[12..20], &[0, 3, 0, 2, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_encode_statistics_sections() {
+        let report = Report {
+            device_id: 0,
+            sequence: 0,
+            channel: 0,
+            breakpoints: &[1000, 5000],
+            buckets: &[3, 4],
+            activity: None,
+            duty_cycle: Some(&[30, 8]),
+            streaks: Some(Streaks {
+                longest_high_ms: 0x0102_0304,
+                since_high_ms: Some(5),
+                since_low_ms: None,
+            }),
+            coverage_ms: Some(&[1000, 2500]),
+        };
+
+        let mut bytes = [0; 48];
+        assert_eq!(report.encode(&mut bytes), Ok(44));
+        assert_eq!(
+            bytes[1],
+            FORMAT_BUCKETS | FORMAT_DUTY_CYCLE | FORMAT_STREAKS | FORMAT_COVERAGE
+        );
+        assert_eq!(
+            &bytes[20..34],
+            &[30, 8, 1, 2, 3, 4, 0, 0, 0, 5, 0xff, 0xff, 0xff, 0xff]
+        );
+        assert_eq!(&bytes[34..42], &[0, 0, 0x03, 0xe8, 0, 0, 0x09, 0xc4]);
+    }
+
+    #[test]
+    fn test_encode_rejects_short_buffer() {
+        let report = Report {
+            device_id: 0,
+            sequence: 0,
+            channel: 0,
+            breakpoints: &[1000],
+            buckets: &[0],
+            activity: None,
+            duty_cycle: None,
+            streaks: None,
+            coverage_ms: None,
+        };
+
+        assert_eq!(
+            report.encode(&mut [0; 15]),
+            Err(Error::BufferLength {
+                expected: 16,
+                actual: 15
+            })
+        );
+    }
+
+    #[test]
+    fn test_encode_rejects_mismatched_sections() {
+        let report = Report {
+            device_id: 0,
+            sequence: 0,
+            channel: 0,
+            breakpoints: &[1000, 5000],
+            buckets: &[3, 4],
+            activity: None,
+            duty_cycle: Some(&[30]),
+            streaks: None,
+            coverage_ms: None,
+        };
+
+        let mut bytes = [0; 32];
+        assert_eq!(
+            report.encode(&mut bytes),
+            Err(Error::SectionLength {
+                expected: 2,
+                actual: 1
+            })
+        );
+
+        let report = Report {
+            buckets: &[3, 4, 5],
+            duty_cycle: None,
+            ..report
+        };
+        assert_eq!(
+            report.encode(&mut bytes),
+            Err(Error::SectionLength {
+                expected: 2,
+                actual: 3
+            })
+        );
+    }
+}
@@ -0,0 +1,414 @@
+//! Minute, hour and day aggregates that outlive the sample history.
+//!
+//! Samples accumulate in the current minute. When a sample lands in a later
+//! minute, the finished minute is kept and added to the current hour, and
+//! finished hours roll up into days the same way. Periods are aligned to
+//! timestamp zero, since the device has no wall clock, and periods without
+//! samples are skipped rather than stored as empty slots.
+//!
+//! [`Rollups::encode`] writes the minutes, hours and days in turn, each as:
+//!
+//! | offset | size      | field                                  |
+//! |--------|-----------|----------------------------------------|
+//! | 0      | 1         | finished period count `n`              |
+//! | 1      | 1         | 1 if a period is still going, else 0   |
+//! | 2      | 20        | the period still going, if there is one |
+//! | ...    | `20 * n`  | finished periods, oldest first         |
+//!
+//! with each period as its start `u64` followed by its High samples,
+//! activations and samples as `u32`s, all big-endian.
+
+use heapless::{HistoryBuf, history_buf::OldestOrdered};
+
+use crate::Error;
+
+pub const MINUTE_MS: u64 = 60_000;
+pub const HOUR_MS: u64 = 60 * MINUTE_MS;
+pub const DAY_MS: u64 = 24 * HOUR_MS;
+
+/// Finished minutes kept.
+pub const MINUTES: usize = 60;
+/// Finished hours kept.
+pub const HOURS: usize = 24;
+/// Finished days kept.
+pub const DAYS: usize = 7;
+
+const SLOT_LEN: usize = 20;
+const LEVEL_HEADER_LEN: usize = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Resolution {
+    Minute,
+    Hour,
+    Day,
+}
+
+impl Resolution {
+    pub fn period_ms(self) -> u64 {
+        match self {
+            Resolution::Minute => MINUTE_MS,
+            Resolution::Hour => HOUR_MS,
+            Resolution::Day => DAY_MS,
+        }
+    }
+}
+
+/// Totals for one minute, hour or day.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Slot {
+    /// Timestamp at which the period starts, in milliseconds.
+    pub start: u64,
+    /// High samples recorded in the period.
+    pub high: u32,
+    /// Rising edges recorded in the period.
+    pub activations: u32,
+    /// All samples recorded in the period, which shows how much of it the
+    /// input was actually watched.
+    pub samples: u32,
+}
+
+impl Slot {
+    fn empty(start: u64) -> Self {
+        Self {
+            start,
+            high: 0,
+            activations: 0,
+            samples: 0,
+        }
+    }
+
+    fn add(&mut self, other: &Slot) {
+        self.high = self.high.saturating_add(other.high);
+        self.activations = self.activations.saturating_add(other.activations);
+        self.samples = self.samples.saturating_add(other.samples);
+    }
+
+    fn encode(&self, bytes: &mut [u8]) {
+        bytes[0..8].copy_from_slice(&self.start.to_be_bytes());
+        bytes[8..12].copy_from_slice(&self.high.to_be_bytes());
+        bytes[12..16].copy_from_slice(&self.activations.to_be_bytes());
+        bytes[16..20].copy_from_slice(&self.samples.to_be_bytes());
+    }
+
+    fn decode(bytes: &[u8]) -> Self {
+        let u32_at = |offset: usize| {
+            u32::from_be_bytes([
+                bytes[offset],
+                bytes[offset + 1],
+                bytes[offset + 2],
+                bytes[offset + 3],
+            ])
+        };
+        let mut start = [0; 8];
+        start.copy_from_slice(&bytes[0..8]);
+
+        Self {
+            start: u64::from_be_bytes(start),
+            high: u32_at(8),
+            activations: u32_at(12),
+            samples: u32_at(16),
+        }
+    }
+}
+
+struct Level<const N: usize> {
+    period: u64,
+    current: Option<Slot>,
+    finished: HistoryBuf<Slot, N>,
+}
+
+impl<const N: usize> Level<N> {
+    const fn new(period: u64) -> Self {
+        Self {
+            period,
+            current: None,
+            finished: HistoryBuf::new(),
+        }
+    }
+
+    /// Finishes the current period if `timestamp` lies in a later one and
+    /// returns it.
+    fn advance(&mut self, timestamp: u64) -> Option<Slot> {
+        let current = self.current?;
+        if timestamp - timestamp % self.period <= current.start {
+            return None;
+        }
+
+        self.finished.write(current);
+        self.current.take()
+    }
+
+    fn add(&mut self, slot: &Slot) {
+        let period = self.period;
+        self.current
+            .get_or_insert_with(|| Slot::empty(slot.start - slot.start % period))
+            .add(slot);
+    }
+
+    /// Start of the latest period, finished or not.
+    fn latest(&self) -> Option<u64> {
+        self.current
+            .or_else(|| self.finished.recent().copied())
+            .map(|slot| slot.start)
+    }
+
+    /// Checks that a decoded `slot` is aligned to a period and follows every
+    /// slot already in the level.
+    fn check(&self, slot: &Slot) -> Result<(), Error> {
+        if !slot.start.is_multiple_of(self.period) || Some(slot.start) <= self.latest() {
+            return Err(Error::InvalidSnapshot);
+        }
+        Ok(())
+    }
+
+    const ENCODED_LEN: usize = LEVEL_HEADER_LEN + (N + 1) * SLOT_LEN;
+
+    fn encode(&self, bytes: &mut [u8]) -> usize {
+        bytes[0] = self.finished.len() as u8;
+        bytes[1] = self.current.is_some() as u8;
+
+        let mut offset = LEVEL_HEADER_LEN;
+        for slot in self.current.iter().chain(self.finished.oldest_ordered()) {
+            slot.encode(&mut bytes[offset..offset + SLOT_LEN]);
+            offset += SLOT_LEN;
+        }
+        offset
+    }
+
+    /// Reads a level written by [`Level::encode`] into this empty level and
+    /// returns the number of bytes read. Periods must be aligned and in
+    /// order, with the one still going after every finished one.
+    fn decode(&mut self, bytes: &[u8]) -> Result<usize, Error> {
+        let header = bytes
+            .get(..LEVEL_HEADER_LEN)
+            .ok_or(Error::InvalidSnapshot)?;
+        let (finished, going) = (header[0] as usize, header[1] as usize);
+        if finished > N || going > 1 {
+            return Err(Error::InvalidSnapshot);
+        }
+        let len = LEVEL_HEADER_LEN + (going + finished) * SLOT_LEN;
+        let mut slots = bytes
+            .get(LEVEL_HEADER_LEN..len)
+            .ok_or(Error::InvalidSnapshot)?
+            .chunks_exact(SLOT_LEN)
+            .map(Slot::decode);
+
+        let current = if going == 1 { slots.next() } else { None };
+        for slot in slots {
+            self.check(&slot)?;
+            self.finished.write(slot);
+        }
+        if let Some(slot) = current {
+            self.check(&slot)?;
+        }
+        self.current = current;
+        Ok(len)
+    }
+}
+
+/// One channel's rollups.
+pub struct Rollups {
+    minutes: Level<MINUTES>,
+    hours: Level<HOURS>,
+    days: Level<DAYS>,
+}
+
+impl Default for Rollups {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Rollups {
+    pub const fn new() -> Self {
+        Self {
+            minutes: Level::new(MINUTE_MS),
+            hours: Level::new(HOUR_MS),
+            days: Level::new(DAY_MS),
+        }
+    }
+
+    pub(crate) fn record(&mut self, timestamp: u64, high: bool, rising: bool) {
+        let sample = Slot {
+            start: timestamp,
+            high: high as u32,
+            activations: rising as u32,
+            samples: 1,
+        };
+
+        // Finish coarser periods only after the finer ones have rolled up
+        if let Some(minute) = self.minutes.advance(timestamp) {
+            self.hours.add(&minute);
+        }
+        if let Some(hour) = self.hours.advance(timestamp) {
+            self.days.add(&hour);
+        }
+        self.days.advance(timestamp);
+        self.minutes.add(&sample);
+    }
+
+    /// Start of the latest period at any resolution.
+    pub(crate) fn latest(&self) -> Option<u64> {
+        self.minutes
+            .latest()
+            .max(self.hours.latest())
+            .max(self.days.latest())
+    }
+
+    /// Size of the largest encoding [`Rollups::encode`] can write.
+    pub const ENCODED_LEN: usize =
+        Level::<MINUTES>::ENCODED_LEN + Level::<HOURS>::ENCODED_LEN + Level::<DAYS>::ENCODED_LEN;
+
+    /// Writes every level, as the module documentation lays out, and returns
+    /// the number of bytes written. `bytes` must hold
+    /// [`Rollups::ENCODED_LEN`] bytes.
+    pub fn encode(&self, bytes: &mut [u8]) -> usize {
+        let mut offset = self.minutes.encode(bytes);
+        offset += self.hours.encode(&mut bytes[offset..]);
+        offset += self.days.encode(&mut bytes[offset..]);
+        offset
+    }
+
+    /// Reads rollups written by [`Rollups::encode`] from the start of
+    /// `bytes`, returning them and the number of bytes read.
+    pub fn decode(bytes: &[u8]) -> Result<(Self, usize), Error> {
+        let mut rollups = Self::new();
+        let mut offset = rollups.minutes.decode(bytes)?;
+        offset += rollups.hours.decode(&bytes[offset..])?;
+        offset += rollups.days.decode(&bytes[offset..])?;
+        Ok((rollups, offset))
+    }
+
+    /// Finished periods at `resolution`, oldest first.
+    pub fn finished(&self, resolution: Resolution) -> OldestOrdered<'_, Slot> {
+        match resolution {
+            Resolution::Minute => self.minutes.finished.oldest_ordered(),
+            Resolution::Hour => self.hours.finished.oldest_ordered(),
+            Resolution::Day => self.days.finished.oldest_ordered(),
+        }
+    }
+
+    /// The period at `resolution` that is still going, including the samples
+    /// that have not rolled up into it yet.
+    pub fn current(&self, resolution: Resolution) -> Option<Slot> {
+        let levels = [self.minutes.current, self.hours.current, self.days.current];
+        let finest = match resolution {
+            Resolution::Minute => 0,
+            Resolution::Hour => 1,
+            Resolution::Day => 2,
+        };
+        let latest = self.minutes.current?.start;
+
+        let mut current = Slot::empty(latest - latest % resolution.period_ms());
+        for slot in levels[..=finest].iter().flatten() {
+            current.add(slot);
+        }
+        Some(current)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_finished_periods_roll_up() {
+        let mut rollups = Rollups::new();
+
+        // One sample a second for two hours, High for the first ten seconds
+        // of every minute, with a rising edge at the start of each
+        for second in 0..2 * 3600 {
+            let high = second % 60 < 10;
+            rollups.record(second * 1000, high, high && second % 60 == 0);
+        }
+        rollups.record(2 * HOUR_MS, false, false);
+
+        let minutes: heapless::Vec<Slot, MINUTES> =
+            rollups.finished(Resolution::Minute).copied().collect();
+        assert_eq!(minutes.len(), MINUTES);
+        assert_eq!(minutes[0].start, 60 * MINUTE_MS);
+        assert_eq!(
+            minutes[MINUTES - 1],
+            Slot {
+                start: 119 * MINUTE_MS,
+                high: 10,
+                activations: 1,
+                samples: 60,
+            }
+        );
+
+        let hours: heapless::Vec<Slot, HOURS> =
+            rollups.finished(Resolution::Hour).copied().collect();
+        assert_eq!(
+            hours.as_slice(),
+            &[
+                Slot {
+                    start: 0,
+                    high: 600,
+                    activations: 60,
+                    samples: 3600,
+                },
+                Slot {
+                    start: HOUR_MS,
+                    high: 600,
+                    activations: 60,
+                    samples: 3600,
+                },
+            ]
+        );
+        assert_eq!(rollups.finished(Resolution::Day).count(), 0);
+    }
+
+    #[test]
+    fn test_current_includes_periods_not_yet_rolled_up() {
+        let mut rollups = Rollups::new();
+        assert_eq!(rollups.current(Resolution::Day), None);
+
+        rollups.record(0, true, true);
+        rollups.record(MINUTE_MS, true, false);
+        rollups.record(MINUTE_MS + 1000, false, false);
+
+        assert_eq!(
+            rollups.current(Resolution::Minute),
+            Some(Slot {
+                start: MINUTE_MS,
+                high: 1,
+                activations: 0,
+                samples: 2,
+            })
+        );
+        assert_eq!(
+            rollups.current(Resolution::Day),
+            Some(Slot {
+                start: 0,
+                high: 2,
+                activations: 1,
+                samples: 3,
+            })
+        );
+
+        // A gap of more than a day skips the empty periods
+        rollups.record(DAY_MS + 5 * MINUTE_MS, true, true);
+        rollups.record(DAY_MS + 6 * MINUTE_MS, false, false);
+        assert_eq!(rollups.finished(Resolution::Minute).count(), 3);
+        assert_eq!(rollups.finished(Resolution::Hour).count(), 1);
+        assert_eq!(
+            rollups.finished(Resolution::Day).copied().next(),
+            Some(Slot {
+                start: 0,
+                high: 2,
+                activations: 1,
+                samples: 3,
+            })
+        );
+        assert_eq!(
+            rollups.current(Resolution::Hour),
+            Some(Slot {
+                start: DAY_MS,
+                high: 1,
+                activations: 1,
+                samples: 2,
+            })
+        );
+    }
+}
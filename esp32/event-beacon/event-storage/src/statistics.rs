@@ -0,0 +1,124 @@
+use crate::{
+    episodes,
+    history::{Block, History},
+    windows::Windows,
+};
+
+/// Streak and recency figures for a channel's whole history, in milliseconds
+/// as of the most recent sample.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Streaks {
+    /// Longest episode of High samples still in history.
+    pub longest_high_ms: u32,
+    /// Time since the last High sample, or `None` if there is none in history.
+    pub since_high_ms: Option<u32>,
+    /// Time since the last Low sample, or `None` if there is none in history.
+    pub since_low_ms: Option<u32>,
+}
+
+impl Streaks {
+    pub fn from_history<const CAPACITY: usize>(history: &History<CAPACITY>) -> Self {
+        let Some(now) = history.blocks().next_back().map(Block::last) else {
+            return Self::default();
+        };
+        let since = |high: bool| {
+            history
+                .runs()
+                .rev()
+                .find(|run| run.high == high)
+                .map(|run| saturate(now - run.last()))
+        };
+
+        Self {
+            longest_high_ms: episodes::episodes(history)
+                .map(|episode| saturate(episode.duration()))
+                .max()
+                .unwrap_or(0),
+            since_high_ms: since(true),
+            since_low_ms: since(false),
+        }
+    }
+}
+
+/// Figures derived from a channel's samples, so that consumers do not have
+/// to estimate them from bucket counts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Statistics<const WINDOWS: usize> {
+    /// Percentage of samples in each window that were High.
+    pub duty_cycle: [u8; WINDOWS],
+    /// Milliseconds of each window that history actually covers.
+    pub coverage_ms: [u32; WINDOWS],
+    pub streaks: Streaks,
+}
+
+impl<const WINDOWS: usize> Statistics<WINDOWS> {
+    pub fn new<const CAPACITY: usize>(
+        history: &History<CAPACITY>,
+        windows: &Windows<WINDOWS>,
+    ) -> Self {
+        Self {
+            duty_cycle: windows.duty_cycle(),
+            coverage_ms: windows.coverage(history),
+            streaks: Streaks::from_history(history),
+        }
+    }
+}
+
+fn saturate(milliseconds: u64) -> u32 {
+    u32::try_from(milliseconds).unwrap_or(u32::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_empty_history_has_no_recency() {
+        let history: History<4> = History::new();
+
+        assert_eq!(Streaks::from_history(&history), Streaks::default());
+    }
+
+    #[test]
+    fn test_streaks_follow_latest_sample() {
+        let mut history: History<8> = History::new();
+        for (timestamp, high) in [
+            (0, false),
+            (100, true),
+            (200, true),
+            (300, true),
+            (400, false),
+            (500, true),
+            (600, false),
+            (700, false),
+        ] {
+            history.push(timestamp, high);
+        }
+
+        assert_eq!(
+            Streaks::from_history(&history),
+            Streaks {
+                longest_high_ms: 300,
+                since_high_ms: Some(200),
+                since_low_ms: Some(0),
+            }
+        );
+
+        history.push(800, true);
+        history.push(900, true);
+        assert_eq!(Streaks::from_history(&history).since_low_ms, Some(200));
+        assert_eq!(Streaks::from_history(&history).since_high_ms, Some(0));
+    }
+
+    #[test]
+    fn test_input_that_never_went_high() {
+        let mut history: History<4> = History::new();
+        history.push(0, false);
+        history.push(100, false);
+
+        let streaks = Streaks::from_history(&history);
+        assert_eq!(streaks.longest_high_ms, 0);
+        assert_eq!(streaks.since_high_ms, None);
+        assert_eq!(streaks.since_low_ms, Some(0));
+    }
+}
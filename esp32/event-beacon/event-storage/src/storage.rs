@@ -1,126 +1,1054 @@
-use core::array::TryFromSliceError;
+use core::{cell::Cell, future::poll_fn, task::Poll};
 
-use embassy_sync::{blocking_mutex::raw::NoopRawMutex, mutex::Mutex};
+use embassy_sync::{
+    blocking_mutex::{
+        self,
+        raw::{NoopRawMutex, RawMutex},
+    },
+    mutex::{MappedMutexGuard, Mutex, MutexGuard},
+    pubsub::PubSubChannel,
+    waitqueue::AtomicWaker,
+    watch::Watch,
+};
 use embassy_time::Instant;
-use heapless::{HistoryBuf, Vec};
+use heapless::mpmc::Queue;
 use uuid::Uuid;
 
+use crate::{
+    Error,
+    alerts::{
+        ALERT_QUEUE_LEN, ALERT_SUBSCRIBERS, Alert, AlertSubscriber, Condition, MAX_RULES, Rule,
+        Tracker,
+    },
+    clock::Clock,
+    decay::Scores,
+    episodes::{self, Activity, Edge},
+    export::{self, Page},
+    histogram::Histograms,
+    history::{Block, History},
+    occupancy::{Classification, Classifier, Occupancy},
+    report::{self, Report},
+    rollup::Rollups,
+    statistics::Statistics,
+    updates::{ReportReceiver, ReportUpdate, ReportWatch},
+    windows::Windows,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum RecordType {
     High,
     Low,
 }
 
-static BUFFER_SIZE: usize = 3000;
-static UPDATE_BUFFER_SIZE: usize = 30;
-static BREAKPOINTS: [u64; 8] = [1000, 5000, 30000, 60000, 120000, 240000, 360000, 600000];
+/// Blocks kept in each channel's history by default.
+///
+/// A block packs up to 256 samples taken on a steady cadence at one bit each,
+/// and a steady input at any length, so sampling every 100 ms the history
+/// reaches back at least 26 minutes however often the input changes, in
+/// under 3 KB per channel. Samples that miss the cadence by more than half an
+/// interval start a new block, and windows reaching past the oldest block
+/// count only what history still holds and say so in
+/// [`Statistics::coverage_ms`].
+pub const DEFAULT_CAPACITY: usize = 60;
+/// Samples that can wait in the staging queue before [`Events::stage`] fails.
+pub const STAGING_LEN: usize = 32;
+pub const DEFAULT_BREAKPOINTS: [u64; 8] =
+    [1000, 5000, 30000, 60000, 120000, 240000, 360000, 600000];
+
+const SNAPSHOT_VERSION: u8 = 4;
+const SNAPSHOT_CHANNEL_LEN: usize = 12;
+const SNAPSHOT_BLOCK_LEN: usize = Block::ENCODED_LEN;
+
+/// Windowed counts of High samples for one or more inputs.
+///
+/// `WINDOWS` is the number of buckets in each report, one per breakpoint,
+/// `CAPACITY` is the number of blocks of samples kept in each channel's
+/// history and `CHANNELS` is the number of inputs tracked. `SCORES` is the
+/// number of decayed activity scores kept for each channel.
+/// Timestamps are tracked with millisecond resolution.
+///
+/// `M` guards the shared state. The default only works within one executor;
+/// a `CriticalSectionRawMutex` lets tasks on other executors, priorities or
+/// cores share the same instance. Interrupt handlers, which cannot wait on a
+/// lock, hand samples over through [`Events::stage`] instead.
+pub struct Events<
+    const WINDOWS: usize = 8,
+    const CAPACITY: usize = DEFAULT_CAPACITY,
+    const CHANNELS: usize = 1,
+    M: RawMutex = NoopRawMutex,
+    const SCORES: usize = 8,
+> {
+    channels: Mutex<M, [Channel<WINDOWS, CAPACITY, SCORES>; CHANNELS]>,
+    rules: Mutex<M, heapless::Vec<Tracker, MAX_RULES>>,
+    alerts: PubSubChannel<M, Alert, ALERT_QUEUE_LEN, ALERT_SUBSCRIBERS, 0>,
+    staged: Queue<Staged, STAGING_LEN>,
+    staged_waker: AtomicWaker,
+    clock: blocking_mutex::Mutex<M, Cell<Clock>>,
+    reports: [ReportWatch<M, WINDOWS>; CHANNELS],
+}
+
+/// A sample taken outside of a task, waiting to be recorded.
+#[derive(Clone, Copy)]
+struct Staged {
+    channel: usize,
+    record_type: RecordType,
+    timestamp: Instant,
+}
+
+struct Channel<const WINDOWS: usize, const CAPACITY: usize, const SCORES: usize> {
+    name: &'static str,
+    history: History<CAPACITY>,
+    windows: Windows<WINDOWS>,
+    sequence: u16,
+    /// Added to every timestamp, so history restored from a snapshot taken
+    /// before a reset stays in the past.
+    origin: u64,
+    histograms: Histograms,
+    /// Timestamp of the most recent rising edge.
+    last_rise: Option<u64>,
+    /// Timestamp of the latest High sample, or of the first sample while none
+    /// has been High.
+    quiet_since: Option<u64>,
+    rollups: Rollups,
+    scores: Scores<SCORES>,
+    occupancy: Option<Occupancy>,
+}
+
+impl<const WINDOWS: usize, const CAPACITY: usize, const SCORES: usize>
+    Channel<WINDOWS, CAPACITY, SCORES>
+{
+    /// Milliseconds on the channel's timeline for a sample at `timestamp`,
+    /// held back no earlier than the latest sample and then lined up with the
+    /// history's cadence, so that every count uses the time the history
+    /// stores. Staged samples can be drained after later ones were recorded
+    /// directly, so timestamps arrive out of order.
+    fn now(&self, timestamp: Instant, high: bool) -> u64 {
+        let now = self.origin + timestamp.as_millis();
+        let now = self
+            .history
+            .blocks()
+            .next_back()
+            .map_or(now, |block| now.max(block.last()));
+        self.history.align(now, high)
+    }
+
+    fn record(&mut self, record_type: RecordType, now: u64) -> Option<Edge> {
+        let high = matches!(record_type, RecordType::High);
+        let previous = self
+            .history
+            .blocks()
+            .next_back()
+            .map(|block| block.level(block.count - 1));
+
+        // Each bucket counts the samples taken less than `breakpoint` milliseconds
+        // before `timestamp`, so uneven sampling never stretches or shrinks a window.
+        let evicted = self.history.push(now, high);
+        self.windows.record(&self.history, evicted, now, high);
+        self.scores.record(now, high);
+        if let Some(occupancy) = self.occupancy.as_mut() {
+            occupancy.update(&self.windows, now);
+        }
+        self.sequence = self.sequence.wrapping_add(1);
+        if high || self.quiet_since.is_none() {
+            self.quiet_since = Some(now);
+        }
+
+        let edge = Edge::between(previous, high);
+        match edge {
+            Some(Edge::Rising) => {
+                if let Some(last_rise) = self.last_rise {
+                    self.histograms
+                        .intervals
+                        .record(now.saturating_sub(last_rise));
+                }
+                self.last_rise = Some(now);
+            }
+            Some(Edge::Falling) => {
+                if let Some(last_rise) = self.last_rise {
+                    self.histograms
+                        .durations
+                        .record(now.saturating_sub(last_rise));
+                }
+            }
+            None => {}
+        }
+        self.rollups.record(now, high, edge == Some(Edge::Rising));
+
+        edge
+    }
+
+    fn activity(&self) -> [Activity; WINDOWS] {
+        episodes::activity(&self.history, self.windows.breakpoints())
+    }
+
+    fn statistics(&self) -> Statistics<WINDOWS> {
+        Statistics::new(&self.history, &self.windows)
+    }
 
-pub struct Events {
-    #[allow(dead_code)]
-    buffer: Mutex<NoopRawMutex, HistoryBuf<u8, BUFFER_SIZE>>,
-    update_timestamps: Mutex<NoopRawMutex, HistoryBuf<Instant, UPDATE_BUFFER_SIZE>>,
-    report: Mutex<NoopRawMutex, Vec<u16, 8>>,
+    fn update(&self, channel: usize) -> ReportUpdate<WINDOWS> {
+        ReportUpdate {
+            channel,
+            sequence: self.sequence,
+            counts: self.windows.counts(),
+        }
+    }
 }
 
 impl Default for Events {
     fn default() -> Self {
-        Self {
-            buffer: Mutex::new(HistoryBuf::new()),
-            update_timestamps: Mutex::new(HistoryBuf::new()),
-            report: Mutex::new(Vec::new()),
-        }
+        Self::new(DEFAULT_BREAKPOINTS)
     }
 }
 
-impl Events {
-    pub async fn record(&self, record_type: RecordType) {
-        self.record_at_time(record_type, Instant::now()).await;
+impl<const WINDOWS: usize, const CAPACITY: usize, M: RawMutex, const SCORES: usize>
+    Events<WINDOWS, CAPACITY, 1, M, SCORES>
+{
+    /// Creates an empty single-channel `Events` with one bucket per breakpoint,
+    /// in milliseconds.
+    pub fn new(breakpoints: [u64; WINDOWS]) -> Self {
+        Self::with_channels([""], breakpoints)
     }
 
-    async fn average_duration_between_updates(&self) -> Option<u64> {
-        let update_timestamps = self.update_timestamps.lock().await;
-        let total_duration_between_updates: u64 = update_timestamps
-            .as_slice()
-            .windows(2)
-            .map(|pair| pair[1].saturating_duration_since(pair[0]))
-            .map(|duration| duration.as_millis())
-            .sum();
+    /// Records a sample, returning the edge it makes with the previous one.
+    pub async fn record(&self, record_type: RecordType) -> Option<Edge> {
+        self.record_at_time(record_type, Instant::now()).await
+    }
 
-        total_duration_between_updates.checked_div(update_timestamps.len() as u64)
+    async fn record_at_time(&self, record_type: RecordType, timestamp: Instant) -> Option<Edge> {
+        self.record_channel_at(0, record_type, timestamp)
+            .await
+            .unwrap_or(None)
     }
 
-    async fn record_at_time(&self, record_type: RecordType, timestamp: Instant) {
-        let mut buffer = self.buffer.lock().await;
-        match record_type {
-            RecordType::High => buffer.write(1),
-            RecordType::Low => buffer.write(0),
-        }
+    pub async fn report(&self) -> [u16; WINDOWS] {
+        self.channels.lock().await[0].windows.counts()
+    }
+
+    pub async fn activity(&self) -> [Activity; WINDOWS] {
+        self.channels.lock().await[0].activity()
+    }
 
-        let average_duration_between_updates = self.average_duration_between_updates().await;
+    pub async fn statistics(&self) -> Statistics<WINDOWS> {
+        self.channels.lock().await[0].statistics()
+    }
 
-        if let Some(duration) = average_duration_between_updates {
-            let breakpoints = BREAKPOINTS
-                .iter()
-                .map(|&breakpoint| {
-                    buffer
-                        .iter()
-                        .rev()
-                        .take(breakpoint.checked_div(duration).unwrap_or(0) as usize)
-                        .map(|&x| x as u16)
-                        .sum::<u16>()
-                })
-                .take(8)
-                .collect::<Vec<u16, 8>>();
+    pub async fn scores(&self) -> [u16; SCORES] {
+        self.channels.lock().await[0].scores.values()
+    }
 
-            let mut report_for_update = self.report.lock().await;
-            *report_for_update = breakpoints
+    /// Returns the decayed activity scores as a fixed-size array, which must
+    /// be exactly `SCORES * 2` bytes, in place of the windowed report.
+    pub async fn scores_as_bytes<const BYTES: usize>(&self) -> Result<[u8; BYTES], Error> {
+        let mut bytes = [0; BYTES];
+        let written = self.write_channel_score_bytes(0, &mut bytes).await?;
+        if written != BYTES {
+            return Err(Error::BufferLength {
+                expected: written,
+                actual: BYTES,
+            });
         }
 
-        let mut update_timestamps_for_update = self.update_timestamps.lock().await;
-        update_timestamps_for_update.write(timestamp);
+        Ok(bytes)
+    }
+
+    pub async fn histograms(&self) -> Histograms {
+        self.channels.lock().await[0].histograms
+    }
+
+    /// Returns the histograms and starts new ones.
+    pub async fn take_histograms(&self) -> Histograms {
+        core::mem::take(&mut self.channels.lock().await[0].histograms)
+    }
+
+    /// Locks the sample history for reading. Recording waits until the guard
+    /// is dropped.
+    pub async fn history(&self) -> MappedMutexGuard<'_, M, History<CAPACITY>> {
+        MutexGuard::map(self.channels.lock().await, |channels| {
+            &mut channels[0].history
+        })
+    }
+
+    /// Locks the minute, hour and day rollups for reading. Recording waits
+    /// until the guard is dropped.
+    pub async fn rollups(&self) -> MappedMutexGuard<'_, M, Rollups> {
+        MutexGuard::map(self.channels.lock().await, |channels| {
+            &mut channels[0].rollups
+        })
     }
 
-    pub async fn as_bytes(&self) -> [u8; 16] {
-        let report = self.report.lock().await;
-        let report_copy = report.clone();
-        drop(report);
+    /// Writes each bucket as a big-endian `u16` and returns the number of bytes written.
+    pub async fn write_bytes(&self, bytes: &mut [u8]) -> Result<usize, Error> {
+        self.write_channel_bytes(0, bytes).await
+    }
 
-        let report_copy = report_copy
-            .iter()
-            .flat_map(|b| b.to_be_bytes())
-            .collect::<Vec<u8, 16>>();
+    /// Returns the report as a fixed-size array, which must be exactly `WINDOWS * 2` bytes.
+    pub async fn as_bytes<const BYTES: usize>(&self) -> Result<[u8; BYTES], Error> {
+        let mut bytes = [0; BYTES];
+        let written = self.write_bytes(&mut bytes).await?;
+        if written != BYTES {
+            return Err(Error::BufferLength {
+                expected: written,
+                actual: BYTES,
+            });
+        }
 
-        report_copy.as_slice().try_into().unwrap()
+        Ok(bytes)
     }
 
-    pub async fn as_uuid(&self) -> Result<Uuid, TryFromSliceError> {
-        let report = self.report.lock().await;
-        let report_copy = report.clone();
-        drop(report);
+    /// The state picked by the classifier set with [`Events::set_classifier`],
+    /// if there is one.
+    pub async fn classification(&self) -> Option<Classification> {
+        self.channel_classification(0).await.unwrap_or(None)
+    }
 
-        let bytes = report_copy
-            .iter()
-            .flat_map(|&v| v.to_be_bytes())
-            .collect::<Vec<u8, 16>>();
+    /// Subscribes to the report published after every sample.
+    pub fn report_receiver(&self) -> Result<ReportReceiver<'_, WINDOWS, M>, Error> {
+        self.channel_report_receiver(0)
+    }
 
-        let bytes = bytes.as_slice().try_into()?;
+    pub async fn as_uuid(&self) -> Result<Uuid, Error> {
+        let bytes = self.as_bytes::<16>().await?;
         let uuid = Uuid::from_bytes(bytes);
 
         Ok(uuid)
     }
 }
 
+impl<
+    const WINDOWS: usize,
+    const CAPACITY: usize,
+    const CHANNELS: usize,
+    M: RawMutex,
+    const SCORES: usize,
+> Events<WINDOWS, CAPACITY, CHANNELS, M, SCORES>
+{
+    /// Creates an empty `Events` tracking one channel per name, all sharing the
+    /// same breakpoints. Channels are addressed by their index in `names`.
+    ///
+    /// Each channel also keeps decayed activity scores, whose half-lives are
+    /// the breakpoints in order unless [`Events::with_half_lives`] picks
+    /// others. Scores beyond the last breakpoint start out disabled.
+    pub fn with_channels(names: [&'static str; CHANNELS], breakpoints: [u64; WINDOWS]) -> Self {
+        Self {
+            channels: Mutex::new(names.map(|name| Channel {
+                name,
+                history: History::new(),
+                windows: Windows::new(breakpoints),
+                sequence: 0,
+                origin: 0,
+                histograms: Histograms::default(),
+                last_rise: None,
+                quiet_since: None,
+                rollups: Rollups::new(),
+                scores: Scores::new(core::array::from_fn(|index| {
+                    breakpoints.get(index).copied().unwrap_or(0)
+                })),
+                occupancy: None,
+            })),
+            rules: Mutex::new(heapless::Vec::new()),
+            alerts: PubSubChannel::new(),
+            staged: Queue::new(),
+            staged_waker: AtomicWaker::new(),
+            clock: blocking_mutex::Mutex::new(Cell::new(Clock::new())),
+            reports: core::array::from_fn(|_| Watch::new()),
+        }
+    }
+
+    /// Replaces the half-lives, in milliseconds, of every channel's decayed
+    /// activity scores. A half-life of zero disables its score.
+    pub fn with_half_lives(mut self, half_lives: [u64; SCORES]) -> Self {
+        for channel in self.channels.get_mut() {
+            channel.scores = Scores::new(half_lives);
+        }
+        self
+    }
+
+    pub async fn breakpoints(&self) -> [u64; WINDOWS] {
+        match self.channels.lock().await.first() {
+            Some(channel) => *channel.windows.breakpoints(),
+            None => [0; WINDOWS],
+        }
+    }
+
+    /// Replaces every channel's breakpoints, in milliseconds, and recounts
+    /// the windows from the history already recorded. Decayed scores keep
+    /// their half-lives.
+    pub async fn set_breakpoints(&self, breakpoints: [u64; WINDOWS]) {
+        let mut channels = self.channels.lock().await;
+        for (index, channel) in channels.iter_mut().enumerate() {
+            let latest = channel.history.blocks().next_back().map_or(0, Block::last);
+            channel.windows = Windows::new(breakpoints);
+            channel.windows.rebuild(&channel.history, latest);
+            self.reports[index].sender().send(channel.update(index));
+        }
+    }
+
+    pub async fn channel_name(&self, channel: usize) -> Result<&'static str, Error> {
+        let channels = self.channels.lock().await;
+        let channel = channels
+            .get(channel)
+            .ok_or(Error::UnknownChannel(channel))?;
+
+        Ok(channel.name)
+    }
+
+    /// Looks up the index of the channel called `name`.
+    pub async fn channel(&self, name: &str) -> Option<usize> {
+        let channels = self.channels.lock().await;
+        channels.iter().position(|channel| channel.name == name)
+    }
+
+    /// Records a sample on one channel, returning the edge it makes with the
+    /// previous one.
+    pub async fn record_channel(
+        &self,
+        channel: usize,
+        record_type: RecordType,
+    ) -> Result<Option<Edge>, Error> {
+        self.record_channel_at(channel, record_type, Instant::now())
+            .await
+    }
+
+    /// Records a sample on one channel taken at `timestamp` rather than now,
+    /// for callers that sample on a schedule of their own. A timestamp older
+    /// than the channel's latest sample is held at that sample.
+    pub async fn record_channel_at(
+        &self,
+        channel: usize,
+        record_type: RecordType,
+        timestamp: Instant,
+    ) -> Result<Option<Edge>, Error> {
+        let mut channels = self.channels.lock().await;
+        let index = channel;
+        let channel = channels
+            .get_mut(channel)
+            .ok_or(Error::UnknownChannel(channel))?;
+        let now = channel.now(timestamp, matches!(record_type, RecordType::High));
+        let edge = channel.record(record_type, now);
+        self.reports[index].sender().send(channel.update(index));
+
+        let publisher = self.alerts.immediate_publisher();
+        for (rule, tracker) in self.rules.lock().await.iter_mut().enumerate() {
+            if tracker.rule.channel != index {
+                continue;
+            }
+            if let Some(active) = tracker.update(&channel.windows, channel.quiet_since, now) {
+                publisher.publish_immediate(Alert {
+                    rule,
+                    channel: index,
+                    active,
+                    timestamp: now,
+                });
+            }
+        }
+
+        Ok(edge)
+    }
+
+    /// Registers an alert rule and returns its index, which identifies the
+    /// rule in the [`Alert`]s it raises.
+    pub async fn add_rule(&self, rule: Rule) -> Result<usize, Error> {
+        if rule.channel >= CHANNELS {
+            return Err(Error::UnknownChannel(rule.channel));
+        }
+        if let Condition::CountAtLeast { bucket, .. } = rule.condition
+            && bucket >= WINDOWS
+        {
+            return Err(Error::UnknownBucket(bucket));
+        }
+
+        let mut rules = self.rules.lock().await;
+        rules
+            .push(Tracker::new(rule))
+            .map_err(|_| Error::TooManyRules)?;
+
+        Ok(rules.len() - 1)
+    }
+
+    /// Subscribes to alerts as rules trip and clear. A subscriber that falls
+    /// more than [`ALERT_QUEUE_LEN`] alerts behind misses the oldest ones.
+    pub fn alert_subscriber(&self) -> Result<AlertSubscriber<'_, M>, Error> {
+        self.alerts
+            .subscriber()
+            .map_err(|_| Error::TooManySubscribers)
+    }
+
+    /// Subscribes to the report published after every sample on one channel.
+    /// At most [`REPORT_RECEIVERS`] receivers can exist per channel at once.
+    ///
+    /// [`REPORT_RECEIVERS`]: crate::updates::REPORT_RECEIVERS
+    pub fn channel_report_receiver(
+        &self,
+        channel: usize,
+    ) -> Result<ReportReceiver<'_, WINDOWS, M>, Error> {
+        let watch = self
+            .reports
+            .get(channel)
+            .ok_or(Error::UnknownChannel(channel))?;
+        let receiver = watch.receiver().ok_or(Error::TooManySubscribers)?;
+
+        Ok(ReportReceiver::new(receiver))
+    }
+
+    /// Classifies one channel as Idle, Active or Busy from now on, replacing
+    /// any classifier it already had. The state is settled on the next sample.
+    pub async fn set_classifier(
+        &self,
+        channel: usize,
+        classifier: Classifier,
+    ) -> Result<(), Error> {
+        if classifier.bucket >= WINDOWS {
+            return Err(Error::UnknownBucket(classifier.bucket));
+        }
+
+        let mut channels = self.channels.lock().await;
+        let channel = channels
+            .get_mut(channel)
+            .ok_or(Error::UnknownChannel(channel))?;
+        channel.occupancy = Some(Occupancy::new(classifier));
+
+        Ok(())
+    }
+
+    /// Returns one channel's state and how long it has been in it, or `None`
+    /// without a classifier or before its first sample.
+    pub async fn channel_classification(
+        &self,
+        channel: usize,
+    ) -> Result<Option<Classification>, Error> {
+        self.channel_classification_at_time(channel, Instant::now())
+            .await
+    }
+
+    async fn channel_classification_at_time(
+        &self,
+        channel: usize,
+        timestamp: Instant,
+    ) -> Result<Option<Classification>, Error> {
+        let channels = self.channels.lock().await;
+        let channel = channels
+            .get(channel)
+            .ok_or(Error::UnknownChannel(channel))?;
+        let now = channel.origin + timestamp.as_millis();
+
+        Ok(channel
+            .occupancy
+            .as_ref()
+            .and_then(|occupancy| occupancy.classification(now)))
+    }
+
+    /// Queues a sample on one channel without taking any lock, so it can be
+    /// called from an interrupt handler. The sample keeps the time it was
+    /// staged and is recorded by [`Events::record_staged`] or
+    /// [`Events::drain_staged`].
+    pub fn stage(&self, channel: usize, record_type: RecordType) -> Result<(), Error> {
+        self.stage_at_time(channel, record_type, Instant::now())
+    }
+
+    fn stage_at_time(
+        &self,
+        channel: usize,
+        record_type: RecordType,
+        timestamp: Instant,
+    ) -> Result<(), Error> {
+        if channel >= CHANNELS {
+            return Err(Error::UnknownChannel(channel));
+        }
+
+        self.staged
+            .enqueue(Staged {
+                channel,
+                record_type,
+                timestamp,
+            })
+            .map_err(|_| Error::StagingFull)?;
+        self.staged_waker.wake();
+
+        Ok(())
+    }
+
+    /// Records every staged sample and returns how many there were.
+    pub async fn drain_staged(&self) -> usize {
+        let mut drained = 0;
+        while let Some(staged) = self.staged.dequeue() {
+            self.record_staged_sample(staged).await;
+            drained += 1;
+        }
+        drained
+    }
+
+    /// Records staged samples as they arrive. Run this in a task alongside
+    /// whatever stages them.
+    pub async fn record_staged(&self) -> ! {
+        loop {
+            let staged = poll_fn(|cx| {
+                self.staged_waker.register(cx.waker());
+                match self.staged.dequeue() {
+                    Some(staged) => Poll::Ready(staged),
+                    None => Poll::Pending,
+                }
+            })
+            .await;
+            self.record_staged_sample(staged).await;
+        }
+    }
+
+    async fn record_staged_sample(&self, staged: Staged) {
+        // The channel was checked when the sample was staged
+        let _ = self
+            .record_channel_at(staged.channel, staged.record_type, staged.timestamp)
+            .await;
+    }
+
+    pub async fn channel_report(&self, channel: usize) -> Result<[u16; WINDOWS], Error> {
+        let channels = self.channels.lock().await;
+        let channel = channels
+            .get(channel)
+            .ok_or(Error::UnknownChannel(channel))?;
+
+        Ok(channel.windows.counts())
+    }
+
+    /// Activations and longest episode in each window of one channel.
+    pub async fn channel_activity(&self, channel: usize) -> Result<[Activity; WINDOWS], Error> {
+        let channels = self.channels.lock().await;
+        let channel = channels
+            .get(channel)
+            .ok_or(Error::UnknownChannel(channel))?;
+
+        Ok(channel.activity())
+    }
+
+    /// Duty cycle, streak and recency figures for one channel.
+    pub async fn channel_statistics(&self, channel: usize) -> Result<Statistics<WINDOWS>, Error> {
+        let channels = self.channels.lock().await;
+        let channel = channels
+            .get(channel)
+            .ok_or(Error::UnknownChannel(channel))?;
+
+        Ok(channel.statistics())
+    }
+
+    /// Decayed activity scores of one channel, one per half-life, as
+    /// fractions of `u16::MAX`. See [`crate::decay`].
+    pub async fn channel_scores(&self, channel: usize) -> Result<[u16; SCORES], Error> {
+        let channels = self.channels.lock().await;
+        let channel = channels
+            .get(channel)
+            .ok_or(Error::UnknownChannel(channel))?;
+
+        Ok(channel.scores.values())
+    }
+
+    /// Writes each of one channel's decayed activity scores as a big-endian
+    /// `u16` and returns the number of bytes written.
+    pub async fn write_channel_score_bytes(
+        &self,
+        channel: usize,
+        bytes: &mut [u8],
+    ) -> Result<usize, Error> {
+        let expected = SCORES * 2;
+        if bytes.len() < expected {
+            return Err(Error::BufferLength {
+                expected,
+                actual: bytes.len(),
+            });
+        }
+
+        let scores = self.channel_scores(channel).await?;
+        for (chunk, value) in bytes.chunks_exact_mut(2).zip(scores) {
+            chunk.copy_from_slice(&value.to_be_bytes());
+        }
+
+        Ok(expected)
+    }
+
+    /// Histograms of the intervals between rising edges and of High episode
+    /// durations on one channel, since they were last taken. They are not part
+    /// of snapshots, so they also start over after a reset.
+    pub async fn channel_histograms(&self, channel: usize) -> Result<Histograms, Error> {
+        let channels = self.channels.lock().await;
+        let channel = channels
+            .get(channel)
+            .ok_or(Error::UnknownChannel(channel))?;
+
+        Ok(channel.histograms)
+    }
+
+    /// Returns one channel's histograms and starts new ones, without losing
+    /// edges recorded in between.
+    pub async fn take_channel_histograms(&self, channel: usize) -> Result<Histograms, Error> {
+        let mut channels = self.channels.lock().await;
+        let channel = channels
+            .get_mut(channel)
+            .ok_or(Error::UnknownChannel(channel))?;
+
+        Ok(core::mem::take(&mut channel.histograms))
+    }
+
+    /// Locks one channel's sample history for reading, for instance to walk
+    /// it with [`History::since`]. Recording on any channel waits until the
+    /// guard is dropped.
+    pub async fn channel_history(
+        &self,
+        channel: usize,
+    ) -> Result<MappedMutexGuard<'_, M, History<CAPACITY>>, Error> {
+        let channels = self.channels.lock().await;
+        if channel >= CHANNELS {
+            return Err(Error::UnknownChannel(channel));
+        }
+
+        Ok(MutexGuard::map(channels, |channels| {
+            &mut channels[channel].history
+        }))
+    }
+
+    /// Writes a page of one channel's history, starting at block `sequence`, in
+    /// the format described in [`crate::export`].
+    pub async fn write_history_page(
+        &self,
+        channel: usize,
+        sequence: u64,
+        bytes: &mut [u8],
+    ) -> Result<Page, Error> {
+        let channels = self.channels.lock().await;
+        let channel = channels
+            .get(channel)
+            .ok_or(Error::UnknownChannel(channel))?;
+
+        export::write_page(&channel.history, sequence, self.clock(), bytes)
+    }
+
+    /// Tells `Events` that it is currently `unix_ms` milliseconds since the
+    /// Unix epoch, for instance from a Current Time Service write. Returns
+    /// the correction in milliseconds if the time had already been set.
+    pub async fn set_unix_time(&self, unix_ms: u64) -> Option<i64> {
+        self.set_unix_time_at(unix_ms, Instant::now()).await
+    }
+
+    async fn set_unix_time_at(&self, unix_ms: u64, timestamp: Instant) -> Option<i64> {
+        // Hold the channels so a restore cannot move the origin meanwhile
+        let channels = self.channels.lock().await;
+        let origin = channels.first().map_or(0, |channel| channel.origin);
+
+        self.update_clock(|clock| clock.sync(origin + timestamp.as_millis(), unix_ms))
+    }
+
+    /// The mapping from stored timestamps, such as run starts, rollup periods
+    /// and alert timestamps, to Unix time.
+    pub fn clock(&self) -> Clock {
+        self.clock.lock(Cell::get)
+    }
+
+    fn update_clock<T>(&self, update: impl FnOnce(&mut Clock) -> T) -> T {
+        self.clock.lock(|cell| {
+            let mut clock = cell.get();
+            let result = update(&mut clock);
+            cell.set(clock);
+            result
+        })
+    }
+
+    /// Locks one channel's minute, hour and day rollups for reading.
+    /// Recording on any channel waits until the guard is dropped.
+    pub async fn channel_rollups(
+        &self,
+        channel: usize,
+    ) -> Result<MappedMutexGuard<'_, M, Rollups>, Error> {
+        let channels = self.channels.lock().await;
+        if channel >= CHANNELS {
+            return Err(Error::UnknownChannel(channel));
+        }
+
+        Ok(MutexGuard::map(channels, |channels| {
+            &mut channels[channel].rollups
+        }))
+    }
+
+    /// Writes each bucket of one channel as a big-endian `u16` and returns the
+    /// number of bytes written.
+    pub async fn write_channel_bytes(
+        &self,
+        channel: usize,
+        bytes: &mut [u8],
+    ) -> Result<usize, Error> {
+        let expected = WINDOWS * 2;
+        if bytes.len() < expected {
+            return Err(Error::BufferLength {
+                expected,
+                actual: bytes.len(),
+            });
+        }
+
+        let report = self.channel_report(channel).await?;
+        for (chunk, value) in bytes.chunks_exact_mut(2).zip(report) {
+            chunk.copy_from_slice(&value.to_be_bytes());
+        }
+
+        Ok(expected)
+    }
+
+    /// Writes every channel's report, each prefixed with its channel index, and
+    /// returns the number of bytes written.
+    pub async fn write_combined_bytes(&self, bytes: &mut [u8]) -> Result<usize, Error> {
+        let stride = 1 + WINDOWS * 2;
+        let expected = stride * CHANNELS;
+        if bytes.len() < expected {
+            return Err(Error::BufferLength {
+                expected,
+                actual: bytes.len(),
+            });
+        }
+
+        let channels = self.channels.lock().await;
+        for (index, (chunk, channel)) in bytes
+            .chunks_exact_mut(stride)
+            .zip(channels.iter())
+            .enumerate()
+        {
+            chunk[0] = index as u8;
+            for (pair, value) in chunk[1..].chunks_exact_mut(2).zip(channel.windows.counts()) {
+                pair.copy_from_slice(&value.to_be_bytes());
+            }
+        }
+
+        Ok(expected)
+    }
+
+    /// Size of the report [`Events::encode_report`] writes: the header, every
+    /// section for each window, the streaks and the CRC.
+    pub const REPORT_LEN: usize =
+        report::HEADER_LEN + WINDOWS * 17 + report::STREAKS_LEN + report::CRC_LEN;
+
+    /// Encodes one channel's report in the versioned format described in
+    /// [`crate::report`] and returns the number of bytes written, which is
+    /// always [`Events::REPORT_LEN`].
+    ///
+    /// The sequence number counts the samples recorded on the channel, so a
+    /// reader can tell a fresh report from a repeat and spot missed updates.
+    pub async fn encode_report(
+        &self,
+        channel: usize,
+        device_id: u16,
+        bytes: &mut [u8],
+    ) -> Result<usize, Error> {
+        let channels = self.channels.lock().await;
+        let state = channels
+            .get(channel)
+            .ok_or(Error::UnknownChannel(channel))?;
+        let statistics = state.statistics();
+
+        Report {
+            device_id,
+            sequence: state.sequence,
+            channel: channel as u8,
+            breakpoints: state.windows.breakpoints(),
+            buckets: &state.windows.counts(),
+            activity: Some(&state.activity()),
+            duty_cycle: Some(&statistics.duty_cycle),
+            streaks: Some(statistics.streaks),
+            coverage_ms: Some(&statistics.coverage_ms),
+        }
+        .encode(bytes)
+    }
+
+    /// Size of the largest snapshot [`Events::snapshot`] can write.
+    pub const SNAPSHOT_LEN: usize = 2 + CHANNELS
+        * (SNAPSHOT_CHANNEL_LEN + (CAPACITY + 1) * SNAPSHOT_BLOCK_LEN + Rollups::ENCODED_LEN);
+
+    /// Serializes every channel's history and rollups and returns the number
+    /// of bytes written. `bytes` must hold at least [`Events::SNAPSHOT_LEN`]
+    /// bytes.
+    ///
+    /// The layout is a version byte and the channel count, then for each
+    /// channel its report sequence number and block count as big-endian
+    /// `u16`s and the history sequence number of its oldest block as a
+    /// big-endian `u64`, followed by its blocks, oldest first, as
+    /// [`Block::encode`] writes them, and its rollups as [`Rollups::encode`]
+    /// writes them.
+    pub async fn snapshot(&self, bytes: &mut [u8]) -> Result<usize, Error> {
+        if bytes.len() < Self::SNAPSHOT_LEN {
+            return Err(Error::BufferLength {
+                expected: Self::SNAPSHOT_LEN,
+                actual: bytes.len(),
+            });
+        }
+
+        let count = u8::try_from(CHANNELS).map_err(|_| Error::TooManyChannels(CHANNELS))?;
+        let channels = self.channels.lock().await;
+        bytes[0] = SNAPSHOT_VERSION;
+        bytes[1] = count;
+
+        let mut offset = 2;
+        for channel in channels.iter() {
+            let blocks = channel.history.len();
+            let blocks = u16::try_from(blocks).map_err(|_| Error::TooManyBlocks(blocks))?;
+            bytes[offset..offset + 2].copy_from_slice(&channel.sequence.to_be_bytes());
+            bytes[offset + 2..offset + 4].copy_from_slice(&blocks.to_be_bytes());
+            bytes[offset + 4..offset + 12]
+                .copy_from_slice(&channel.history.first_sequence().to_be_bytes());
+            offset += SNAPSHOT_CHANNEL_LEN;
+
+            for block in channel.history.blocks() {
+                block.encode(&mut bytes[offset..offset + SNAPSHOT_BLOCK_LEN]);
+                offset += SNAPSHOT_BLOCK_LEN;
+            }
+            offset += channel.rollups.encode(&mut bytes[offset..]);
+        }
+
+        Ok(offset)
+    }
+
+    /// Replaces every channel's history and rollups with a snapshot from
+    /// [`Events::snapshot`] and rebuilds the reports from it.
+    ///
+    /// The time spent powered off is unknown, so the restored history picks up
+    /// right where the snapshot left off. Nothing changes if the snapshot is
+    /// invalid.
+    pub async fn restore(&self, bytes: &[u8]) -> Result<(), Error> {
+        self.restore_at_time(bytes, Instant::now()).await
+    }
+
+    async fn restore_at_time(&self, bytes: &[u8], timestamp: Instant) -> Result<(), Error> {
+        if bytes.len() < 2 || bytes[0] != SNAPSHOT_VERSION || bytes[1] as usize != CHANNELS {
+            return Err(Error::InvalidSnapshot);
+        }
+
+        // Validate everything before touching any channel
+        let mut latest = None;
+        let mut offset = 2;
+        for _ in 0..CHANNELS {
+            let header = bytes
+                .get(offset..offset + SNAPSHOT_CHANNEL_LEN)
+                .ok_or(Error::InvalidSnapshot)?;
+            let blocks = u16::from_be_bytes([header[2], header[3]]) as usize;
+            let mut first_sequence = [0; 8];
+            first_sequence.copy_from_slice(&header[4..12]);
+            u64::from_be_bytes(first_sequence)
+                .checked_add(blocks as u64)
+                .ok_or(Error::InvalidSnapshot)?;
+            offset += SNAPSHOT_CHANNEL_LEN;
+
+            let blocks = bytes
+                .get(offset..offset + blocks * SNAPSHOT_BLOCK_LEN)
+                .ok_or(Error::InvalidSnapshot)?;
+            let mut last = None;
+            for block in blocks.chunks_exact(SNAPSHOT_BLOCK_LEN).map(Block::decode) {
+                match block {
+                    Some(block) if block.is_valid() => last = Some(block.last()),
+                    _ => return Err(Error::InvalidSnapshot),
+                }
+            }
+            latest = latest.max(last);
+            offset += blocks.len();
+
+            // Rollups cannot hold periods later than the samples behind them
+            let (rollups, len) = Rollups::decode(&bytes[offset..])?;
+            if rollups.latest() > last {
+                return Err(Error::InvalidSnapshot);
+            }
+            offset += len;
+        }
+
+        let origin = latest.map_or(0, |latest| {
+            (latest + 1).saturating_sub(timestamp.as_millis())
+        });
+        let mut channels = self.channels.lock().await;
+        if let Some(channel) = channels.first() {
+            self.update_clock(|clock| clock.shift(origin as i64 - channel.origin as i64));
+        }
+        let mut offset = 2;
+        for (index, channel) in channels.iter_mut().enumerate() {
+            let header = &bytes[offset..offset + SNAPSHOT_CHANNEL_LEN];
+            let blocks = u16::from_be_bytes([header[2], header[3]]) as usize;
+            let mut first_sequence = [0; 8];
+            first_sequence.copy_from_slice(&header[4..12]);
+            offset += SNAPSHOT_CHANNEL_LEN;
+
+            let blocks = &bytes[offset..offset + blocks * SNAPSHOT_BLOCK_LEN];
+            channel.history.restore(
+                u64::from_be_bytes(first_sequence),
+                blocks
+                    .chunks_exact(SNAPSHOT_BLOCK_LEN)
+                    .filter_map(Block::decode),
+            );
+            channel
+                .windows
+                .rebuild(&channel.history, latest.unwrap_or(0));
+            channel.sequence = u16::from_be_bytes([header[0], header[1]]);
+            channel.origin = origin;
+            channel.scores.rebuild(&channel.history);
+            if let Some(occupancy) = channel.occupancy.as_mut() {
+                occupancy.rebuild(&channel.windows, latest.unwrap_or(0));
+            }
+            channel.last_rise = episodes::episodes(&channel.history)
+                .next()
+                .map(|episode| episode.start);
+            channel.quiet_since = channel
+                .history
+                .runs()
+                .rev()
+                .find(|run| run.high)
+                .map(|run| run.last())
+                .or_else(|| channel.history.blocks().next().map(|block| block.start));
+            offset += blocks.len();
+            let (rollups, len) = Rollups::decode(&bytes[offset..])?;
+            channel.rollups = rollups;
+            offset += len;
+            self.reports[index].sender().send(channel.update(index));
+        }
+
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{
+        histogram::{BINS, Histogram},
+        mock::MockFlash,
+        occupancy::State,
+        persist::FlashStore,
+        rollup::{HOUR_MS, MINUTE_MS, Resolution, Slot},
+        statistics::Streaks,
+        updates::REPORT_RECEIVERS,
+    };
+    use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
     use embassy_time::Duration;
     use futures::executor::block_on;
 
+    struct XorShift(u64);
+
+    impl XorShift {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+    }
+
+    /// Counts High samples per window by scanning every sample, as `Events`
+    /// did before the history was packed into blocks.
+    fn reference_report(samples: &[(u64, bool)], breakpoints: &[u64; 8]) -> [u16; 8] {
+        let now = samples.last().map(|&(timestamp, _)| timestamp).unwrap_or(0);
+        breakpoints.map(|breakpoint| {
+            samples
+                .iter()
+                .rev()
+                .take_while(|&&(timestamp, _)| now - timestamp < breakpoint)
+                .filter(|&&(_, high)| high)
+                .count() as u16
+        })
+    }
+
     #[test]
     fn test_new_events_initializes_with_zero_values() {
         block_on(async {
             let events = Events::default();
 
-            let report = events.as_bytes().await;
+            let report = events.report().await;
             assert_eq!(report.len(), 8);
             for value in report.iter() {
                 assert_eq!(*value, 0);
@@ -134,7 +1062,6 @@ mod tests {
             let start_time = Instant::from_ticks(0);
             let events = Events::default();
 
-            // Need at least 3 records to establish timestamp history
             events.record_at_time(RecordType::Low, start_time).await;
             events
                 .record_at_time(RecordType::Low, start_time + Duration::from_millis(100))
@@ -143,7 +1070,7 @@ mod tests {
                 .record_at_time(RecordType::High, start_time + Duration::from_millis(200))
                 .await;
 
-            let report = events.as_bytes().await;
+            let report = events.report().await;
             for value in report.iter() {
                 assert_eq!(*value, 1);
             }
@@ -156,7 +1083,6 @@ mod tests {
             let start_time = Instant::from_ticks(0);
             let events = Events::default();
 
-            // Need at least 3 records to establish timestamp history
             events.record_at_time(RecordType::Low, start_time).await;
             events
                 .record_at_time(RecordType::Low, start_time + Duration::from_millis(100))
@@ -165,7 +1091,7 @@ mod tests {
                 .record_at_time(RecordType::Low, start_time + Duration::from_millis(200))
                 .await;
 
-            let report = events.as_bytes().await;
+            let report = events.report().await;
             for value in report.iter() {
                 assert_eq!(*value, 0);
             }
@@ -178,7 +1104,6 @@ mod tests {
             let start_time = Instant::from_ticks(0);
             let events = Events::default();
 
-            // Need at least 3 records to establish timestamp history
             events.record_at_time(RecordType::Low, start_time).await;
             events
                 .record_at_time(RecordType::Low, start_time + Duration::from_millis(100))
@@ -193,7 +1118,7 @@ mod tests {
                 .record_at_time(RecordType::High, start_time + Duration::from_millis(400))
                 .await;
 
-            let report = events.as_bytes().await;
+            let report = events.report().await;
             for value in report.iter() {
                 assert_eq!(*value, 3);
             }
@@ -206,7 +1131,7 @@ mod tests {
             let start_time = Instant::from_ticks(0);
             let events = Events::default();
 
-            // Establish timestamp history with regular 100ms intervals
+            // Record at regular 100ms intervals
             events.record_at_time(RecordType::Low, start_time).await;
             events
                 .record_at_time(RecordType::Low, start_time + Duration::from_millis(100))
@@ -218,13 +1143,13 @@ mod tests {
                 .await;
 
             // The high event is in the buffer, report should show 1 for all buckets
-            let report = events.as_bytes().await;
+            let report = events.report().await;
             for value in report.iter() {
                 assert_eq!(*value, 1);
             }
 
             // Add more low events to push the high event out of the first bucket's window
-            // First bucket is 1000ms, with ~100ms avg duration we need ~10 more entries
+            // First bucket is 1000ms, so the high event must be more than 1000ms old
             for i in 0..12 {
                 events
                     .record_at_time(
@@ -234,12 +1159,12 @@ mod tests {
                     .await;
             }
 
-            let report = events.as_bytes().await;
+            let report = events.report().await;
             // First bucket should now be 0 (high event pushed out of window)
             assert_eq!(report[0], 0);
             // Larger buckets should still contain the high event
-            for i in 1..8 {
-                assert_eq!(report[i], 1);
+            for value in report.iter().skip(1) {
+                assert_eq!(*value, 1);
             }
         });
     }
@@ -250,7 +1175,7 @@ mod tests {
             let start_time = Instant::from_ticks(0);
             let events = Events::default();
 
-            // Establish timestamp history with regular 100ms intervals
+            // Record at regular 100ms intervals
             events.record_at_time(RecordType::Low, start_time).await;
             events
                 .record_at_time(RecordType::Low, start_time + Duration::from_millis(100))
@@ -262,7 +1187,7 @@ mod tests {
                 .await;
 
             // Add enough low events to push high event out of first two buckets
-            // Second bucket is 5000ms, with ~100ms avg we need ~50 more entries
+            // Second bucket is 5000ms, so the high event must be more than 5000ms old
             for i in 0..60 {
                 events
                     .record_at_time(
@@ -272,13 +1197,13 @@ mod tests {
                     .await;
             }
 
-            let report = events.as_bytes().await;
+            let report = events.report().await;
             // First two buckets should now be 0 (high event pushed out of their windows)
             assert_eq!(report[0], 0);
             assert_eq!(report[1], 0);
             // Larger buckets should still contain the high event
-            for i in 2..8 {
-                assert_eq!(report[i], 1);
+            for value in report.iter().skip(2) {
+                assert_eq!(*value, 1);
             }
         });
     }
@@ -293,7 +1218,7 @@ mod tests {
             let future_time = start_time + Duration::from_millis(600);
             events.record_at_time(RecordType::Low, future_time).await;
 
-            let report = events.as_bytes().await;
+            let report = events.report().await;
             // All buckets should remain at 0
             for value in report.iter() {
                 assert_eq!(*value, 0);
@@ -307,7 +1232,7 @@ mod tests {
             let start_time = Instant::from_ticks(0);
             let events = Events::default();
 
-            // Establish timestamp history with regular 100ms intervals
+            // Record at regular 100ms intervals
             events.record_at_time(RecordType::Low, start_time).await;
             events
                 .record_at_time(RecordType::Low, start_time + Duration::from_millis(100))
@@ -325,13 +1250,13 @@ mod tests {
                 .await;
 
             // All buckets should have value 3
-            let report = events.as_bytes().await;
+            let report = events.report().await;
             for value in report.iter() {
                 assert_eq!(*value, 3);
             }
 
             // Add more entries to push one high event out of the first bucket
-            // First bucket is 1000ms, we need about 10 entries total in that window
+            // First bucket is 1000ms, so the first high event ages out at 1200ms
             for i in 0..8 {
                 events
                     .record_at_time(
@@ -341,12 +1266,12 @@ mod tests {
                     .await;
             }
 
-            let report = events.as_bytes().await;
+            let report = events.report().await;
             // First bucket should now have 2 (one high event pushed out)
             assert_eq!(report[0], 2);
             // Other buckets should still have 3
-            for i in 1..8 {
-                assert_eq!(report[i], 3);
+            for value in report.iter().skip(1) {
+                assert_eq!(*value, 3);
             }
         });
     }
@@ -357,7 +1282,7 @@ mod tests {
             let start_time = Instant::from_ticks(0);
             let events = Events::default();
 
-            // Establish timestamp history with regular 100ms intervals
+            // Record at regular 100ms intervals
             events.record_at_time(RecordType::Low, start_time).await;
             events
                 .record_at_time(RecordType::Low, start_time + Duration::from_millis(100))
@@ -368,10 +1293,23 @@ mod tests {
                 .record_at_time(RecordType::High, start_time + Duration::from_millis(200))
                 .await;
 
-            // The largest bucket is 600000ms (600s). Add enough entries to push it out.
-            // With 100ms intervals, we need 6000+ entries, but buffer is only 3000.
-            // So let's fill the buffer with Low events which will push out the High event.
-            for i in 0..3000 {
+            // The largest bucket is 600000ms (600s). The Low samples share a single
+            // run, so the high event stays in history until it ages out of every window.
+            for i in 0..2999 {
+                events
+                    .record_at_time(
+                        RecordType::Low,
+                        start_time + Duration::from_millis(300 + i * 100),
+                    )
+                    .await;
+            }
+
+            let report = events.report().await;
+            assert_eq!(report[5], 0);
+            assert_eq!(report[6], 1);
+            assert_eq!(report[7], 1);
+
+            for i in 2999..6000 {
                 events
                     .record_at_time(
                         RecordType::Low,
@@ -380,8 +1318,8 @@ mod tests {
                     .await;
             }
 
-            let report = events.as_bytes().await;
-            // All buckets should be 0 (high event pushed completely out of buffer)
+            let report = events.report().await;
+            // All buckets should be 0 (high event is more than 600s old)
             for value in report.iter() {
                 assert_eq!(*value, 0);
             }
@@ -394,7 +1332,6 @@ mod tests {
             let start_time = Instant::from_ticks(0);
             let events = Events::default();
 
-            // Establish timestamp history first
             events.record_at_time(RecordType::Low, start_time).await;
             events
                 .record_at_time(RecordType::Low, start_time + Duration::from_millis(100))
@@ -412,11 +1349,1333 @@ mod tests {
                 .record_at_time(RecordType::Low, start_time + Duration::from_millis(500))
                 .await;
 
-            let report = events.as_bytes().await;
+            let report = events.report().await;
             // Should have 2 high events recorded
             for value in report.iter() {
                 assert_eq!(*value, 2);
             }
         });
     }
+
+    #[test]
+    fn test_windows_follow_timestamps_not_sample_counts() {
+        block_on(async {
+            let start_time = Instant::from_ticks(0);
+            let events = Events::default();
+
+            // A burst of samples 10ms apart followed by an executor stall
+            for i in 0..5 {
+                events
+                    .record_at_time(RecordType::High, start_time + Duration::from_millis(i * 10))
+                    .await;
+            }
+            events
+                .record_at_time(RecordType::Low, start_time + Duration::from_millis(3040))
+                .await;
+
+            let report = events.report().await;
+            // The burst is more than 1000ms old but well inside the 5000ms window
+            assert_eq!(report[0], 0);
+            for value in report.iter().skip(1) {
+                assert_eq!(*value, 5);
+            }
+        });
+    }
+
+    #[test]
+    fn test_window_boundary_is_exclusive() {
+        block_on(async {
+            let start_time = Instant::from_ticks(0);
+            let events = Events::default();
+
+            events.record_at_time(RecordType::High, start_time).await;
+            events
+                .record_at_time(RecordType::High, start_time + Duration::from_millis(1))
+                .await;
+            events
+                .record_at_time(RecordType::Low, start_time + Duration::from_millis(1000))
+                .await;
+
+            let report = events.report().await;
+            // The sample exactly 1000ms old has left the 1000ms window
+            assert_eq!(report[0], 1);
+            assert_eq!(report[1], 2);
+        });
+    }
+
+    #[test]
+    fn test_changed_breakpoints_recount_history() {
+        block_on(async {
+            let events: Events<2, 64> = Events::new([1000, 5000]);
+            let start_time = Instant::from_millis(0);
+            for i in 0..60 {
+                let record_type = if i % 4 == 0 {
+                    RecordType::High
+                } else {
+                    RecordType::Low
+                };
+                events
+                    .record_at_time(record_type, start_time + Duration::from_millis(i * 100))
+                    .await;
+            }
+            assert_eq!(events.report().await, [2, 12]);
+
+            events.set_breakpoints([2000, 500]).await;
+            assert_eq!(events.breakpoints().await, [2000, 500]);
+            assert_eq!(events.report().await, [5, 1]);
+
+            // Later samples keep counting against the new windows
+            events
+                .record_at_time(RecordType::High, start_time + Duration::from_millis(6000))
+                .await;
+            assert_eq!(events.report().await, [5, 2]);
+        });
+    }
+
+    #[test]
+    fn test_custom_breakpoints_and_capacity() {
+        block_on(async {
+            let start_time = Instant::from_ticks(0);
+            let events: Events<3, 4> = Events::new([250, 500, u64::MAX]);
+
+            for i in 1..=12u64 {
+                let record_type = if i.is_multiple_of(2) {
+                    RecordType::High
+                } else {
+                    RecordType::Low
+                };
+                let timestamp = 100 * ((1 << i) - 1);
+                events
+                    .record_at_time(record_type, start_time + Duration::from_millis(timestamp))
+                    .await;
+            }
+
+            // Gaps double every sample, so each block holds two samples and
+            // only the four most recent completed blocks and the open one are
+            // retained, with High samples 4 to 12
+            assert_eq!(events.report().await, [1, 1, 5]);
+        });
+    }
+
+    #[test]
+    fn test_write_bytes_adapts_to_bucket_count() {
+        block_on(async {
+            let start_time = Instant::from_ticks(0);
+            let events: Events<2, 16> = Events::new([1000, 5000]);
+
+            events.record_at_time(RecordType::High, start_time).await;
+            events
+                .record_at_time(RecordType::High, start_time + Duration::from_millis(2000))
+                .await;
+
+            let mut bytes = [0xff; 6];
+            assert_eq!(events.write_bytes(&mut bytes).await, Ok(4));
+            assert_eq!(bytes, [0, 1, 0, 2, 0xff, 0xff]);
+
+            assert_eq!(events.as_bytes::<4>().await, Ok([0, 1, 0, 2]));
+            assert_eq!(
+                events.write_bytes(&mut [0; 3]).await,
+                Err(Error::BufferLength {
+                    expected: 4,
+                    actual: 3
+                })
+            );
+            assert!(events.as_bytes::<6>().await.is_err());
+            assert!(events.as_uuid().await.is_err());
+        });
+    }
+
+    #[test]
+    fn test_block_history_matches_per_sample_reference() {
+        block_on(async {
+            let mut rng = XorShift(0x2545_f491_4f6c_dd1d);
+            let events: Events<8, 512> = Events::new(DEFAULT_BREAKPOINTS);
+            let mut samples: heapless::Vec<(u64, bool), 8000> = heapless::Vec::new();
+            let mut timestamp = 0;
+            let mut high = false;
+
+            for step in 0..8000u32 {
+                // Mostly a steady 100ms cadence with occasional jitter and stalls
+                timestamp += match rng.next() % 50 {
+                    0 => 100 + rng.next() % 2000,
+                    1..=3 => 95 + rng.next() % 10,
+                    _ => 100,
+                };
+                if rng.next().is_multiple_of(20) {
+                    high = !high;
+                }
+
+                let record_type = if high {
+                    RecordType::High
+                } else {
+                    RecordType::Low
+                };
+                events
+                    .record_at_time(record_type, Instant::from_millis(timestamp))
+                    .await;
+                // Jittery samples are counted at the slot they snapped to
+                let stored = events.history().await.blocks().next_back().unwrap().last();
+                samples.push((stored, high)).unwrap();
+
+                if !step.is_multiple_of(7) {
+                    continue;
+                }
+                assert_eq!(
+                    events.report().await,
+                    reference_report(&samples, &DEFAULT_BREAKPOINTS)
+                );
+            }
+        });
+    }
+
+    #[test]
+    fn test_channels_are_tracked_independently() {
+        block_on(async {
+            let start_time = Instant::from_ticks(0);
+            let events: Events<2, 16, 3> =
+                Events::with_channels(["door", "motion", "button"], [1000, 5000]);
+
+            for i in 0..3 {
+                let timestamp = start_time + Duration::from_millis(i * 100);
+                events
+                    .record_channel_at(0, RecordType::High, timestamp)
+                    .await
+                    .unwrap();
+                events
+                    .record_channel_at(1, RecordType::Low, timestamp)
+                    .await
+                    .unwrap();
+            }
+            events
+                .record_channel_at(2, RecordType::High, start_time)
+                .await
+                .unwrap();
+
+            assert_eq!(events.channel_report(0).await, Ok([3, 3]));
+            assert_eq!(events.channel_report(1).await, Ok([0, 0]));
+            assert_eq!(events.channel_report(2).await, Ok([1, 1]));
+            assert_eq!(
+                events.channel_report(3).await,
+                Err(Error::UnknownChannel(3))
+            );
+            assert_eq!(
+                events
+                    .record_channel_at(3, RecordType::High, start_time)
+                    .await,
+                Err(Error::UnknownChannel(3))
+            );
+        });
+    }
+
+    #[test]
+    fn test_channels_are_looked_up_by_name() {
+        block_on(async {
+            let events: Events<2, 16, 2> = Events::with_channels(["door", "motion"], [1000, 5000]);
+
+            assert_eq!(events.channel("motion").await, Some(1));
+            assert_eq!(events.channel("button").await, None);
+            assert_eq!(events.channel_name(0).await, Ok("door"));
+            assert_eq!(events.channel_name(2).await, Err(Error::UnknownChannel(2)));
+        });
+    }
+
+    #[test]
+    fn test_combined_bytes_identify_each_channel() {
+        block_on(async {
+            let start_time = Instant::from_ticks(0);
+            let events: Events<2, 16, 2> = Events::with_channels(["door", "motion"], [1000, 5000]);
+
+            events
+                .record_channel_at(1, RecordType::High, start_time)
+                .await
+                .unwrap();
+
+            let mut bytes = [0xff; 10];
+            assert_eq!(events.write_combined_bytes(&mut bytes).await, Ok(10));
+            assert_eq!(bytes, [0, 0, 0, 0, 0, 1, 0, 1, 0, 1]);
+            assert_eq!(
+                events.write_combined_bytes(&mut [0; 9]).await,
+                Err(Error::BufferLength {
+                    expected: 10,
+                    actual: 9
+                })
+            );
+        });
+    }
+
+    #[test]
+    fn test_encoded_report_carries_sequence_and_buckets() {
+        block_on(async {
+            let start_time = Instant::from_ticks(0);
+            let events: Events<2, 16, 2> = Events::with_channels(["door", "motion"], [1000, 5000]);
+
+            for i in 0..3 {
+                events
+                    .record_channel_at(
+                        1,
+                        RecordType::High,
+                        start_time + Duration::from_millis(i * 100),
+                    )
+                    .await
+                    .unwrap();
+            }
+
+            let mut bytes = [0; 64];
+            let written = events.encode_report(1, 0xbeef, &mut bytes).await.unwrap();
+            assert_eq!(written, 56);
+            assert_eq!(written, Events::<2, 16, 2>::REPORT_LEN);
+            assert_eq!(&bytes[..8], &[1, 0b11111, 0xbe, 0xef, 0, 3, 1, 2]);
+            assert_eq!(&bytes[16..20], &[0, 3, 0, 3]);
+            assert_eq!(&bytes[20..32], &[0, 1, 0, 0, 0, 200, 0, 1, 0, 0, 0, 200]);
+            assert_eq!(&bytes[32..34], &[100, 100]);
+            // Both windows cover just the 200ms since the first sample
+            assert_eq!(&bytes[46..54], &[0, 0, 0, 200, 0, 0, 0, 200]);
+
+            assert_eq!(
+                events.encode_report(2, 0xbeef, &mut bytes).await,
+                Err(Error::UnknownChannel(2))
+            );
+        });
+    }
+
+    #[test]
+    fn test_record_reports_edges() {
+        block_on(async {
+            let start_time = Instant::from_ticks(0);
+            let events = Events::default();
+
+            let edges = [
+                (RecordType::Low, None),
+                (RecordType::High, Some(Edge::Rising)),
+                (RecordType::High, None),
+                (RecordType::Low, Some(Edge::Falling)),
+            ];
+            for (i, (record_type, edge)) in edges.into_iter().enumerate() {
+                assert_eq!(
+                    events
+                        .record_at_time(
+                            record_type,
+                            start_time + Duration::from_millis(i as u64 * 100)
+                        )
+                        .await,
+                    edge
+                );
+            }
+        });
+    }
+
+    #[test]
+    fn test_activity_separates_short_and_long_activations() {
+        block_on(async {
+            let start_time = Instant::from_ticks(0);
+            let events: Events<1, 64, 2> = Events::with_channels(["door", "motion"], [60_000]);
+
+            // The door opens five times for 200ms, motion stays High for 30s
+            for i in 0..400u64 {
+                let timestamp = start_time + Duration::from_millis(i * 100);
+                let door = if i < 50 && i % 10 >= 8 {
+                    RecordType::High
+                } else {
+                    RecordType::Low
+                };
+                let motion = if (100..400).contains(&i) {
+                    RecordType::High
+                } else {
+                    RecordType::Low
+                };
+                events.record_channel_at(0, door, timestamp).await.unwrap();
+                events
+                    .record_channel_at(1, motion, timestamp)
+                    .await
+                    .unwrap();
+            }
+
+            assert_eq!(events.channel_report(0).await, Ok([10]));
+            assert_eq!(
+                events.channel_activity(0).await,
+                Ok([Activity {
+                    activations: 5,
+                    longest_ms: 200
+                }])
+            );
+            assert_eq!(
+                events.channel_activity(1).await,
+                Ok([Activity {
+                    activations: 1,
+                    longest_ms: 29_900
+                }])
+            );
+        });
+    }
+
+    #[test]
+    fn test_histograms_show_periodic_activity() {
+        block_on(async {
+            let start_time = Instant::from_ticks(0);
+            let events: Events<1, 16> = Events::new([60_000]);
+
+            // A 200ms pulse every second
+            for i in 0..100u64 {
+                let record_type = if i % 10 < 2 {
+                    RecordType::High
+                } else {
+                    RecordType::Low
+                };
+                events
+                    .record_at_time(record_type, start_time + Duration::from_millis(i * 100))
+                    .await;
+            }
+
+            let histograms = events.take_histograms().await;
+            assert_eq!(histograms.intervals.counts[Histogram::bin(1000)], 9);
+            assert_eq!(histograms.intervals.total(), 9);
+            assert_eq!(histograms.durations.counts[Histogram::bin(200)], 10);
+            assert_eq!(histograms.durations.total(), 10);
+            assert_eq!(events.histograms().await, Histograms::default());
+
+            // Taking the histograms keeps the last rising edge
+            events
+                .record_at_time(RecordType::High, start_time + Duration::from_millis(10_000))
+                .await;
+            assert_eq!(
+                events.histograms().await.intervals.counts[Histogram::bin(1000)],
+                1
+            );
+            assert_eq!(
+                events.take_channel_histograms(1).await,
+                Err(Error::UnknownChannel(1))
+            );
+        });
+    }
+
+    #[test]
+    fn test_histograms_ignore_timestamps_going_backwards() {
+        block_on(async {
+            let start_time = Instant::from_ticks(0);
+            let events: Events<1, 16> = Events::new([60_000]);
+
+            for (millis, record_type) in [
+                (1000, RecordType::High),
+                (1200, RecordType::Low),
+                (2000, RecordType::High),
+                // Earlier than the rising edge it ends
+                (500, RecordType::Low),
+                (3000, RecordType::High),
+            ] {
+                events
+                    .record_at_time(record_type, start_time + Duration::from_millis(millis))
+                    .await;
+            }
+
+            let histograms = events.histograms().await;
+            assert_eq!(histograms.durations.counts[Histogram::bin(200)], 1);
+            assert_eq!(histograms.durations.counts[Histogram::bin(0)], 1);
+            assert_eq!(histograms.durations.counts[BINS - 1], 0);
+            assert_eq!(histograms.intervals.counts[Histogram::bin(1000)], 2);
+            assert_eq!(histograms.intervals.total(), 2);
+        });
+    }
+
+    #[test]
+    fn test_rollups_outlast_history() {
+        block_on(async {
+            let start_time = Instant::from_ticks(0);
+            let events: Events<1, 4, 2> = Events::with_channels(["a", "b"], [1000]);
+
+            // Busy for the first hour, quiet for the second
+            for minute in 0..120u64 {
+                let record_type = if minute < 60 && minute % 2 == 0 {
+                    RecordType::High
+                } else {
+                    RecordType::Low
+                };
+                events
+                    .record_channel_at(
+                        1,
+                        record_type,
+                        start_time + Duration::from_millis(minute * MINUTE_MS),
+                    )
+                    .await
+                    .unwrap();
+            }
+
+            let rollups = events.channel_rollups(1).await.unwrap();
+            let mut hours = rollups.finished(Resolution::Hour);
+            assert_eq!(
+                hours.next(),
+                Some(&Slot {
+                    start: 0,
+                    high: 30,
+                    activations: 30,
+                    samples: 60,
+                })
+            );
+            assert_eq!(hours.next(), None);
+            assert_eq!(
+                rollups.current(Resolution::Hour),
+                Some(Slot {
+                    start: HOUR_MS,
+                    high: 0,
+                    activations: 0,
+                    samples: 60,
+                })
+            );
+            drop(rollups);
+
+            assert_eq!(
+                events
+                    .channel_rollups(0)
+                    .await
+                    .unwrap()
+                    .current(Resolution::Day),
+                None
+            );
+            assert!(matches!(
+                events.channel_rollups(2).await,
+                Err(Error::UnknownChannel(2))
+            ));
+        });
+    }
+
+    #[test]
+    fn test_history_is_paged_out_by_sequence() {
+        block_on(async {
+            let start_time = Instant::from_ticks(0);
+            let events: Events<1, 4, 2> = Events::with_channels(["a", "b"], [1000]);
+
+            // Gaps double every sample, so each block holds two samples
+            let spaced = |i: u64| 100 * ((1 << i) - 1);
+            for i in 0..20 {
+                let record_type = if i % 2 == 0 {
+                    RecordType::High
+                } else {
+                    RecordType::Low
+                };
+                events
+                    .record_channel_at(
+                        1,
+                        record_type,
+                        start_time + Duration::from_millis(spaced(i)),
+                    )
+                    .await
+                    .unwrap();
+            }
+
+            let mut bytes = [0; export::PAGE_HEADER_LEN + 3 * Block::ENCODED_LEN];
+            let page = events.write_history_page(1, 0, &mut bytes).await.unwrap();
+            assert_eq!((page.first, page.blocks, page.next), (5, 3, 8));
+
+            let history = events.channel_history(1).await.unwrap();
+            let block = Block::decode(&bytes[export::PAGE_HEADER_LEN..]).unwrap();
+            assert_eq!(Some(&block), history.get(5));
+            assert_eq!((block.start, block.count), (spaced(10), 2));
+            let (sequence, block) = history.since(page.next).last().unwrap();
+            assert_eq!((sequence, block.start), (9, spaced(18)));
+            drop(history);
+
+            assert_eq!(
+                events
+                    .write_history_page(0, 0, &mut bytes)
+                    .await
+                    .map(|page| page.blocks),
+                Ok(0)
+            );
+            assert_eq!(
+                events.write_history_page(2, 0, &mut bytes).await,
+                Err(Error::UnknownChannel(2))
+            );
+        });
+    }
+
+    #[test]
+    fn test_unix_time_survives_restore() {
+        block_on(async {
+            let start_time = Instant::from_ticks(0);
+            let events: Events<1, 16> = Events::new([1000]);
+            for i in 0..5 {
+                events
+                    .record_at_time(RecordType::High, start_time + Duration::from_secs(i))
+                    .await;
+            }
+            assert_eq!(events.clock().to_unix_ms(0), None);
+
+            let unix_ms = 1_700_000_000_000;
+            assert_eq!(
+                events
+                    .set_unix_time_at(unix_ms, start_time + Duration::from_secs(4))
+                    .await,
+                None
+            );
+            let mut bytes = [0; 64];
+            events.write_history_page(0, 0, &mut bytes).await.unwrap();
+            assert_eq!(bytes[9], export::FLAG_UNIX_TIME);
+            assert_eq!(
+                Block::decode(&bytes[export::PAGE_HEADER_LEN..])
+                    .unwrap()
+                    .start,
+                unix_ms - 4000
+            );
+
+            // Restoring moves stored timestamps, but the clock keeps mapping
+            // the same instant to the same Unix time
+            let mut snapshot = [0; Events::<1, 16>::SNAPSHOT_LEN];
+            let written = events.snapshot(&mut snapshot).await.unwrap();
+            let now = start_time + Duration::from_secs(1);
+            events
+                .restore_at_time(&snapshot[..written], now)
+                .await
+                .unwrap();
+            let origin = events.channels.lock().await[0].origin;
+            assert_eq!(origin, 3001);
+            assert_eq!(
+                events.clock().to_unix_ms(origin + 2000),
+                Some(unix_ms - 2000)
+            );
+
+            // Corrections move the clock without touching the windows
+            let now = start_time + Duration::from_secs(10);
+            assert_eq!(
+                events.set_unix_time_at(unix_ms + 5750, now).await,
+                Some(-250)
+            );
+            assert_eq!(events.report().await, [1]);
+        });
+    }
+
+    #[test]
+    fn test_scores_fit_the_advertised_payload() {
+        block_on(async {
+            type ThreeScores = Events<8, DEFAULT_CAPACITY, 1, NoopRawMutex, 3>;
+            let start_time = Instant::from_ticks(0);
+            let events =
+                ThreeScores::new(DEFAULT_BREAKPOINTS).with_half_lives([1000, 10_000, 300_000]);
+
+            for i in 0..=10 {
+                events
+                    .record_at_time(RecordType::High, start_time + Duration::from_secs(i))
+                    .await;
+            }
+
+            let scores = events.scores().await;
+            // 1 - 2^-10, 1 - 2^-1 and 1 - 2^(-1/30) of the way to always High
+            assert_eq!(scores, [65471, 32768, 1497]);
+
+            let bytes = events.scores_as_bytes::<6>().await.unwrap();
+            assert_eq!(&bytes[2..4], &32768u16.to_be_bytes());
+            assert_eq!(
+                events.scores_as_bytes::<5>().await,
+                Err(Error::BufferLength {
+                    expected: 6,
+                    actual: 5
+                })
+            );
+
+            // Restoring a snapshot rebuilds the scores from history
+            let mut snapshot = [0; ThreeScores::SNAPSHOT_LEN];
+            let written = events.snapshot(&mut snapshot).await.unwrap();
+            let restored =
+                ThreeScores::new(DEFAULT_BREAKPOINTS).with_half_lives([1000, 10_000, 300_000]);
+            restored
+                .restore_at_time(&snapshot[..written], start_time)
+                .await
+                .unwrap();
+            assert_eq!(restored.scores().await, scores);
+        });
+    }
+
+    #[test]
+    fn test_statistics_summarize_channel() {
+        block_on(async {
+            let start_time = Instant::from_ticks(0);
+            let events: Events<2, 16> = Events::new([1000, 10_000]);
+
+            // High for 2s out of every 5s
+            for i in 0..100u64 {
+                let record_type = if i % 50 < 20 {
+                    RecordType::High
+                } else {
+                    RecordType::Low
+                };
+                events
+                    .record_at_time(record_type, start_time + Duration::from_millis(i * 100))
+                    .await;
+            }
+
+            assert_eq!(
+                events.statistics().await,
+                Statistics {
+                    duty_cycle: [0, 40],
+                    coverage_ms: [1000, 9900],
+                    streaks: Streaks {
+                        longest_high_ms: 2000,
+                        since_high_ms: Some(3000),
+                        since_low_ms: Some(0),
+                    },
+                }
+            );
+        });
+    }
+
+    #[test]
+    fn test_default_capacity_covers_jittery_toggling() {
+        block_on(async {
+            let start_time = Instant::from_ticks(0);
+            let events: Events = Events::default();
+            assert!(core::mem::size_of::<History<DEFAULT_CAPACITY>>() <= 3000);
+
+            // Toggling every 100 ms for 30 minutes, each sample up to 40 ms late
+            let mut rng = XorShift(0x2545_f491_4f6c_dd1d);
+            for i in 0..18_000u64 {
+                let record_type = if i % 2 == 0 {
+                    RecordType::High
+                } else {
+                    RecordType::Low
+                };
+                let jitter = rng.next() % 40;
+                events
+                    .record_at_time(
+                        record_type,
+                        start_time + Duration::from_millis(i * 100 + jitter),
+                    )
+                    .await;
+            }
+
+            let statistics = events.statistics().await;
+            assert_eq!(
+                statistics.coverage_ms,
+                DEFAULT_BREAKPOINTS.map(|ms| ms as u32)
+            );
+            assert_eq!(statistics.duty_cycle[7], 50);
+
+            let history = events.history().await;
+            let oldest = history.blocks().next().unwrap().start;
+            let newest = history.blocks().next_back().unwrap().last();
+            assert!(newest - oldest > 25 * 60 * 1000, "{oldest}..{newest}");
+        });
+    }
+
+    #[test]
+    fn test_rules_notify_subscribers() {
+        block_on(async {
+            let start_time = Instant::from_ticks(0);
+            let events: Events<2, 16, 2> = Events::with_channels(["door", "motion"], [1000, 5000]);
+
+            let busy = events
+                .add_rule(Rule {
+                    channel: 1,
+                    condition: Condition::CountAtLeast {
+                        bucket: 1,
+                        trip: 3,
+                        clear: 0,
+                    },
+                    hold_off_ms: 0,
+                })
+                .await
+                .unwrap();
+            let mut subscriber = events.alert_subscriber().unwrap();
+
+            for i in 0..60 {
+                let record_type = if i < 3 {
+                    RecordType::High
+                } else {
+                    RecordType::Low
+                };
+                let timestamp = start_time + Duration::from_millis(i * 100);
+                events
+                    .record_channel_at(0, RecordType::High, timestamp)
+                    .await
+                    .unwrap();
+                events
+                    .record_channel_at(1, record_type, timestamp)
+                    .await
+                    .unwrap();
+            }
+
+            assert_eq!(
+                subscriber.try_next_message_pure(),
+                Some(Alert {
+                    rule: busy,
+                    channel: 1,
+                    active: true,
+                    timestamp: 200
+                })
+            );
+            assert_eq!(
+                subscriber.try_next_message_pure(),
+                Some(Alert {
+                    rule: busy,
+                    channel: 1,
+                    active: false,
+                    timestamp: 5200
+                })
+            );
+            assert_eq!(subscriber.try_next_message_pure(), None);
+        });
+    }
+
+    #[test]
+    fn test_invalid_rules_are_rejected() {
+        block_on(async {
+            let events: Events<2, 16> = Events::new([1000, 5000]);
+            let rule = Rule {
+                channel: 0,
+                condition: Condition::NoHighFor { trip_ms: 1000 },
+                hold_off_ms: 0,
+            };
+
+            assert_eq!(
+                events.add_rule(Rule { channel: 1, ..rule }).await,
+                Err(Error::UnknownChannel(1))
+            );
+            assert_eq!(
+                events
+                    .add_rule(Rule {
+                        condition: Condition::CountAtLeast {
+                            bucket: 2,
+                            trip: 1,
+                            clear: 0
+                        },
+                        ..rule
+                    })
+                    .await,
+                Err(Error::UnknownBucket(2))
+            );
+
+            for index in 0..MAX_RULES {
+                assert_eq!(events.add_rule(rule).await, Ok(index));
+            }
+            assert_eq!(events.add_rule(rule).await, Err(Error::TooManyRules));
+        });
+    }
+
+    #[test]
+    fn test_report_receivers_wait_for_new_samples() {
+        block_on(async {
+            let events: Events<2, 16, 2> = Events::with_channels(["a", "b"], [1000, 5000]);
+            let start_time = Instant::from_millis(0);
+            let mut every = events.channel_report_receiver(1).unwrap();
+            let mut changes = events.channel_report_receiver(1).unwrap().with_min_delta(1);
+
+            let recording = async {
+                for i in 0..4 {
+                    let record_type = if i == 2 {
+                        RecordType::High
+                    } else {
+                        RecordType::Low
+                    };
+                    let timestamp = start_time + Duration::from_millis(i * 100);
+                    events
+                        .record_channel_at(0, RecordType::High, timestamp)
+                        .await
+                        .unwrap();
+                    events
+                        .record_channel_at(1, record_type, timestamp)
+                        .await
+                        .unwrap();
+                    embassy_futures::yield_now().await;
+                }
+            };
+            let every = async {
+                let mut sequences = heapless::Vec::<u16, 4>::new();
+                for _ in 0..4 {
+                    sequences.push(every.changed().await.sequence).unwrap();
+                }
+                sequences
+            };
+            let changes = async { (changes.changed().await, changes.changed().await) };
+            let ((), sequences, (first, second)) = futures::join!(recording, every, changes);
+
+            // Every sample on channel 1 is seen, and channel 0 never is
+            assert_eq!(sequences.as_slice(), &[1, 2, 3, 4]);
+            assert_eq!(
+                first,
+                ReportUpdate {
+                    channel: 1,
+                    sequence: 1,
+                    counts: [0, 0],
+                }
+            );
+            assert_eq!(second.sequence, 3);
+            assert_eq!(second.counts, [1, 1]);
+        });
+    }
+
+    #[test]
+    fn test_report_receivers_are_limited() {
+        let events: Events<2, 16> = Events::new([1000, 5000]);
+        assert!(matches!(
+            events.channel_report_receiver(1),
+            Err(Error::UnknownChannel(1))
+        ));
+
+        let receivers: [_; REPORT_RECEIVERS] = core::array::from_fn(|_| events.report_receiver());
+        assert!(receivers.iter().all(Result::is_ok));
+        assert!(matches!(
+            events.report_receiver(),
+            Err(Error::TooManySubscribers)
+        ));
+
+        // Dropping a receiver frees its slot
+        drop(receivers);
+        assert!(events.report_receiver().is_ok());
+    }
+
+    #[test]
+    fn test_classifier_tracks_channel_state() {
+        block_on(async {
+            let events: Events<2, 16, 2> = Events::with_channels(["a", "b"], [1000, 5000]);
+            let classifier = Classifier {
+                bucket: 0,
+                active_at: 20,
+                busy_at: 70,
+                min_dwell_ms: [0, 0, 0],
+            };
+
+            assert_eq!(
+                events.set_classifier(2, classifier).await,
+                Err(Error::UnknownChannel(2))
+            );
+            assert_eq!(
+                events
+                    .set_classifier(
+                        1,
+                        Classifier {
+                            bucket: 2,
+                            ..classifier
+                        }
+                    )
+                    .await,
+                Err(Error::UnknownBucket(2))
+            );
+            events.set_classifier(1, classifier).await.unwrap();
+
+            let start_time = Instant::from_millis(0);
+            for i in 0..20 {
+                let timestamp = start_time + Duration::from_millis(i * 100);
+                let record_type = if i < 15 {
+                    RecordType::Low
+                } else {
+                    RecordType::High
+                };
+                events
+                    .record_channel_at(0, record_type, timestamp)
+                    .await
+                    .unwrap();
+                events
+                    .record_channel_at(1, record_type, timestamp)
+                    .await
+                    .unwrap();
+            }
+
+            // Five High samples in the last ten, first reaching 20% at 1600ms
+            assert_eq!(
+                events
+                    .channel_classification_at_time(1, start_time + Duration::from_millis(2500))
+                    .await,
+                Ok(Some(Classification {
+                    state: State::Active,
+                    elapsed_ms: 900,
+                }))
+            );
+            assert_eq!(events.channel_classification(0).await, Ok(None));
+        });
+    }
+
+    #[test]
+    fn test_staged_samples_keep_their_timestamps() {
+        block_on(async {
+            let start_time = Instant::from_ticks(0);
+            let events: Events<2, 16, 2> = Events::with_channels(["door", "motion"], [1000, 5000]);
+
+            for i in 0..3 {
+                events
+                    .stage_at_time(
+                        1,
+                        RecordType::High,
+                        start_time + Duration::from_millis(i * 100),
+                    )
+                    .unwrap();
+            }
+            events
+                .stage_at_time(0, RecordType::Low, start_time + Duration::from_millis(1100))
+                .unwrap();
+            assert_eq!(
+                events.stage_at_time(2, RecordType::High, start_time),
+                Err(Error::UnknownChannel(2))
+            );
+
+            // Nothing is recorded until the queue is drained
+            assert_eq!(events.channel_report(1).await, Ok([0, 0]));
+            assert_eq!(events.drain_staged().await, 4);
+            assert_eq!(events.drain_staged().await, 0);
+            assert_eq!(events.channel_report(1).await, Ok([3, 3]));
+            assert_eq!(
+                events.channel_statistics(0).await.unwrap().duty_cycle,
+                [0, 0]
+            );
+        });
+    }
+
+    #[test]
+    fn test_late_staged_samples_are_held_at_latest_timestamp() {
+        block_on(async {
+            let start_time = Instant::from_ticks(0);
+            let events: Events<2, 16> = Events::new([1000, 5000]);
+            events
+                .add_rule(Rule {
+                    channel: 0,
+                    condition: Condition::NoHighFor { trip_ms: 10_000 },
+                    hold_off_ms: 0,
+                })
+                .await
+                .unwrap();
+            let mut subscriber = events.alert_subscriber().unwrap();
+
+            for i in 10..20 {
+                events
+                    .record_at_time(
+                        RecordType::High,
+                        start_time + Duration::from_millis(i * 100),
+                    )
+                    .await;
+            }
+            // Staged by an interrupt long before they were drained
+            events
+                .stage_at_time(0, RecordType::High, start_time + Duration::from_millis(200))
+                .unwrap();
+            events
+                .stage_at_time(0, RecordType::Low, start_time + Duration::from_millis(300))
+                .unwrap();
+            assert_eq!(events.drain_staged().await, 2);
+
+            // Both count as taken with the latest sample, at 1900ms
+            assert_eq!(events.report().await, [11, 11]);
+            let durations = events.histograms().await.durations;
+            assert_eq!(durations.total(), 1);
+            assert_eq!(durations.counts[Histogram::bin(900)], 1);
+            assert_eq!(subscriber.try_next_message_pure(), None);
+        });
+    }
+
+    #[test]
+    fn test_staging_queue_overflow_is_reported() {
+        let events: Events<2, 16> = Events::new([1000, 5000]);
+
+        for _ in 0..STAGING_LEN {
+            events
+                .stage_at_time(0, RecordType::High, Instant::from_ticks(0))
+                .unwrap();
+        }
+        assert_eq!(
+            events.stage_at_time(0, RecordType::High, Instant::from_ticks(0)),
+            Err(Error::StagingFull)
+        );
+    }
+
+    #[test]
+    fn test_events_are_shared_across_threads() {
+        let events: Events<2, 512, 4, CriticalSectionRawMutex> =
+            Events::with_channels(["a", "b", "c", "d"], [1000, u64::MAX]);
+
+        std::thread::scope(|scope| {
+            for channel in 0..4 {
+                let events = &events;
+                scope.spawn(move || {
+                    block_on(async {
+                        for i in 0..250 {
+                            let timestamp = Instant::from_millis(i * 10);
+                            events
+                                .record_channel_at(channel, RecordType::High, timestamp)
+                                .await
+                                .unwrap();
+                            // Interrupts on another core staging alongside the tasks
+                            while events
+                                .stage_at_time(channel, RecordType::High, timestamp)
+                                .is_err()
+                            {
+                                std::thread::yield_now();
+                            }
+                            events.drain_staged().await;
+                        }
+                    });
+                });
+            }
+        });
+
+        block_on(async {
+            events.drain_staged().await;
+            for channel in 0..4 {
+                assert_eq!(events.channel_report(channel).await.unwrap()[1], 500);
+            }
+        });
+    }
+
+    #[test]
+    fn test_restored_snapshot_continues_history() {
+        block_on(async {
+            let start_time = Instant::from_ticks(0);
+            let events: Events<2, 16, 2> = Events::with_channels(["door", "motion"], [1000, 5000]);
+
+            for i in 0..20 {
+                let timestamp = start_time + Duration::from_millis(10_000 + i * 100);
+                let record_type = if i >= 15 {
+                    RecordType::High
+                } else {
+                    RecordType::Low
+                };
+                events
+                    .record_channel_at(0, record_type, timestamp)
+                    .await
+                    .unwrap();
+                events
+                    .record_channel_at(1, RecordType::High, timestamp)
+                    .await
+                    .unwrap();
+            }
+
+            let mut bytes = [0; Events::<2, 16, 2>::SNAPSHOT_LEN];
+            let written = events.snapshot(&mut bytes).await.unwrap();
+
+            // After a reset the clock starts over
+            let restored: Events<2, 16, 2> =
+                Events::with_channels(["door", "motion"], [1000, 5000]);
+            restored
+                .restore_at_time(&bytes[..written], start_time)
+                .await
+                .unwrap();
+            assert_eq!(restored.channel_report(0).await, Ok([5, 5]));
+            assert_eq!(restored.channel_report(1).await, Ok([10, 20]));
+
+            let mut encoded = [0; 64];
+            restored.encode_report(1, 0, &mut encoded).await.unwrap();
+            assert_eq!(&encoded[4..6], &[0, 20]);
+
+            // Samples after the restore extend the history and age the old ones out
+            for i in 0..10 {
+                restored
+                    .record_channel_at(
+                        0,
+                        RecordType::Low,
+                        start_time + Duration::from_millis(99 + i * 100),
+                    )
+                    .await
+                    .unwrap();
+            }
+            assert_eq!(restored.channel_report(0).await, Ok([0, 5]));
+
+            // The episode that was going at the reset ends 500ms after it began
+            let durations = restored.channel_histograms(0).await.unwrap().durations;
+            assert_eq!(durations.counts[Histogram::bin(500)], 1);
+            assert_eq!(durations.total(), 1);
+        });
+    }
+
+    #[test]
+    fn test_history_pages_resume_after_restore() {
+        block_on(async {
+            let start_time = Instant::from_ticks(0);
+            let events: Events<1, 4> = Events::new([1000]);
+
+            // Gaps double every sample, so each block holds two samples
+            let spaced = |i: u64| 100 * ((1 << i) - 1);
+            for i in 0..20 {
+                let record_type = if i % 2 == 0 {
+                    RecordType::High
+                } else {
+                    RecordType::Low
+                };
+                events
+                    .record_at_time(record_type, start_time + Duration::from_millis(spaced(i)))
+                    .await;
+            }
+
+            let mut bytes = [0; export::PAGE_HEADER_LEN + 3 * Block::ENCODED_LEN];
+            let last_seen = events.write_history_page(0, 0, &mut bytes).await.unwrap();
+            assert_eq!(last_seen.next, 8);
+
+            let mut snapshot = [0; Events::<1, 4>::SNAPSHOT_LEN];
+            let written = events.snapshot(&mut snapshot).await.unwrap();
+            let restored: Events<1, 4> = Events::new([1000]);
+            restored
+                .restore_at_time(&snapshot[..written], start_time)
+                .await
+                .unwrap();
+
+            // The reader picks up where it left off before the reset
+            let page = restored
+                .write_history_page(0, last_seen.next, &mut bytes)
+                .await
+                .unwrap();
+            assert_eq!((page.first, page.blocks, page.next), (8, 2, 9));
+            assert_eq!(
+                Block::decode(&bytes[export::PAGE_HEADER_LEN..])
+                    .unwrap()
+                    .start,
+                spaced(16)
+            );
+
+            // New blocks carry on the numbering
+            restored
+                .record_at_time(RecordType::High, start_time + Duration::from_millis(500))
+                .await;
+            assert_eq!(restored.history().await.last_sequence(), Some(10));
+        });
+    }
+
+    #[test]
+    fn test_rollups_survive_restore() {
+        block_on(async {
+            let start_time = Instant::from_ticks(0);
+            let events: Events<1, 4> = Events::new([1000]);
+            for minute in 0..90u64 {
+                let record_type = if minute % 3 == 0 {
+                    RecordType::High
+                } else {
+                    RecordType::Low
+                };
+                events
+                    .record_at_time(
+                        record_type,
+                        start_time + Duration::from_millis(minute * MINUTE_MS),
+                    )
+                    .await;
+            }
+
+            let mut snapshot = [0; Events::<1, 4>::SNAPSHOT_LEN];
+            let written = events.snapshot(&mut snapshot).await.unwrap();
+            let restored: Events<1, 4> = Events::new([1000]);
+            restored
+                .restore_at_time(&snapshot[..written], start_time)
+                .await
+                .unwrap();
+
+            let (original, rollups) = (events.rollups().await, restored.rollups().await);
+            for resolution in [Resolution::Minute, Resolution::Hour, Resolution::Day] {
+                assert!(
+                    original
+                        .finished(resolution)
+                        .eq(rollups.finished(resolution))
+                );
+                assert_eq!(original.current(resolution), rollups.current(resolution));
+            }
+            assert_eq!(rollups.finished(Resolution::Minute).count(), 60);
+            assert_eq!(rollups.finished(Resolution::Hour).next().unwrap().high, 20);
+            drop((original, rollups));
+
+            // Samples after the restore add to the hour still going
+            restored.record_at_time(RecordType::High, start_time).await;
+            assert_eq!(
+                restored.rollups().await.current(Resolution::Hour),
+                Some(Slot {
+                    start: HOUR_MS,
+                    high: 11,
+                    activations: 11,
+                    samples: 31,
+                })
+            );
+
+            // A period that does not start on a period boundary
+            let blocks = u16::from_be_bytes([snapshot[4], snapshot[5]]) as usize;
+            let rollups_offset = 2 + SNAPSHOT_CHANNEL_LEN + blocks * SNAPSHOT_BLOCK_LEN;
+            let mut misaligned = snapshot;
+            misaligned[rollups_offset + 2 + 7] += 1;
+            assert_eq!(
+                restored
+                    .restore_at_time(&misaligned[..written], start_time)
+                    .await,
+                Err(Error::InvalidSnapshot)
+            );
+        });
+    }
+
+    #[test]
+    fn test_invalid_snapshots_are_rejected() {
+        block_on(async {
+            let events: Events<2, 16, 2> = Events::with_channels(["door", "motion"], [1000, 5000]);
+            events
+                .record_channel_at(0, RecordType::High, Instant::from_ticks(0))
+                .await
+                .unwrap();
+
+            let mut bytes = [0; Events::<2, 16, 2>::SNAPSHOT_LEN];
+            let written = events.snapshot(&mut bytes).await.unwrap();
+            assert_eq!(
+                events.snapshot(&mut [0; 8]).await,
+                Err(Error::BufferLength {
+                    expected: Events::<2, 16, 2>::SNAPSHOT_LEN,
+                    actual: 8
+                })
+            );
+
+            let single: Events<2, 16> = Events::new([1000, 5000]);
+            assert_eq!(
+                single
+                    .restore_at_time(&bytes[..written], Instant::from_ticks(0))
+                    .await,
+                Err(Error::InvalidSnapshot)
+            );
+            assert_eq!(
+                events
+                    .restore_at_time(&bytes[..written - 1], Instant::from_ticks(0))
+                    .await,
+                Err(Error::InvalidSnapshot)
+            );
+
+            // Two samples can only share a block a whole interval apart
+            let mut stalled = bytes;
+            stalled[2 + SNAPSHOT_CHANNEL_LEN + 13] = 2;
+            assert_eq!(
+                events
+                    .restore_at_time(&stalled[..written], Instant::from_ticks(0))
+                    .await,
+                Err(Error::InvalidSnapshot)
+            );
+
+            let mut future = bytes;
+            future[0] = SNAPSHOT_VERSION + 1;
+            assert_eq!(
+                events
+                    .restore_at_time(&future[..written], Instant::from_ticks(0))
+                    .await,
+                Err(Error::InvalidSnapshot)
+            );
+
+            // A failed restore leaves the history alone
+            assert_eq!(events.channel_report(0).await, Ok([1, 1]));
+        });
+    }
+
+    #[test]
+    fn test_snapshot_survives_flash_round_trip() {
+        block_on(async {
+            let events = Events::default();
+            for i in 0..600 {
+                let record_type = if (i / 50) % 2 == 0 {
+                    RecordType::High
+                } else {
+                    RecordType::Low
+                };
+                events
+                    .record_at_time(record_type, Instant::from_millis(i * 100))
+                    .await;
+            }
+
+            let mut flash: MockFlash<{ 2 * 4096 }> = MockFlash::new();
+            let mut bytes = [0; Events::<8, DEFAULT_CAPACITY>::SNAPSHOT_LEN];
+            let written = events.snapshot(&mut bytes).await.unwrap();
+            let mut store = FlashStore::new(&mut flash, 0, 4096, 2).unwrap();
+            store.save(&bytes[..written]).unwrap();
+
+            let mut store = FlashStore::new(&mut flash, 0, 4096, 2).unwrap();
+            let mut loaded = [0; Events::<8, DEFAULT_CAPACITY>::SNAPSHOT_LEN];
+            let len = store.load(&mut loaded).unwrap().unwrap();
+
+            let restored = Events::default();
+            restored
+                .restore_at_time(&loaded[..len], Instant::from_ticks(0))
+                .await
+                .unwrap();
+            assert_eq!(restored.report().await, events.report().await);
+        });
+    }
 }
@@ -0,0 +1,134 @@
+//! Change notifications for windowed reports.
+//!
+//! Every recorded sample publishes the channel's new report. Only the latest
+//! report is kept, so a receiver that falls behind skips straight to it
+//! rather than working through a backlog.
+
+use embassy_sync::{
+    blocking_mutex::raw::{NoopRawMutex, RawMutex},
+    watch::{Receiver, Watch},
+};
+
+/// Number of tasks that can wait on each channel's reports at the same time.
+pub const REPORT_RECEIVERS: usize = 4;
+
+pub(crate) type ReportWatch<M, const WINDOWS: usize> =
+    Watch<M, ReportUpdate<WINDOWS>, REPORT_RECEIVERS>;
+
+/// A channel's report after a sample was recorded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReportUpdate<const WINDOWS: usize> {
+    pub channel: usize,
+    /// Number of samples recorded on the channel, wrapping, as in
+    /// [`crate::report::Report::sequence`].
+    pub sequence: u16,
+    /// High samples in each window.
+    pub counts: [u16; WINDOWS],
+}
+
+/// Waits for new reports on one channel.
+pub struct ReportReceiver<'a, const WINDOWS: usize, M: RawMutex = NoopRawMutex> {
+    receiver: Receiver<'a, M, ReportUpdate<WINDOWS>, REPORT_RECEIVERS>,
+    min_delta: u16,
+    /// Counts of the last report returned.
+    last: Option<[u16; WINDOWS]>,
+}
+
+impl<'a, const WINDOWS: usize, M: RawMutex> ReportReceiver<'a, WINDOWS, M> {
+    pub(crate) fn new(receiver: Receiver<'a, M, ReportUpdate<WINDOWS>, REPORT_RECEIVERS>) -> Self {
+        Self {
+            receiver,
+            min_delta: 0,
+            last: None,
+        }
+    }
+
+    /// Skips reports until some bucket has moved by at least `min_delta`
+    /// since the last report returned. A `min_delta` of 1 ignores samples
+    /// that leave every count as it was; 0, the default, returns them all.
+    pub fn with_min_delta(mut self, min_delta: u16) -> Self {
+        self.min_delta = min_delta;
+        self
+    }
+
+    /// Waits for the next report that passes the filter. The first call
+    /// returns the latest report straight away if one has been published.
+    pub async fn changed(&mut self) -> ReportUpdate<WINDOWS> {
+        let (last, min_delta) = (self.last, self.min_delta);
+        let update = self
+            .receiver
+            .changed_and(|update| moved(last, update, min_delta))
+            .await;
+        self.last = Some(update.counts);
+        update
+    }
+
+    /// Returns the next report that passes the filter without waiting.
+    pub fn try_changed(&mut self) -> Option<ReportUpdate<WINDOWS>> {
+        let (last, min_delta) = (self.last, self.min_delta);
+        let update = self
+            .receiver
+            .try_changed_and(|update| moved(last, update, min_delta))?;
+        self.last = Some(update.counts);
+        Some(update)
+    }
+}
+
+fn moved<const WINDOWS: usize>(
+    last: Option<[u16; WINDOWS]>,
+    update: &ReportUpdate<WINDOWS>,
+    min_delta: u16,
+) -> bool {
+    let Some(last) = last else {
+        return true;
+    };
+    last.iter()
+        .zip(update.counts)
+        .any(|(last, count)| last.abs_diff(count) >= min_delta)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(sequence: u16, counts: [u16; 2]) -> ReportUpdate<2> {
+        ReportUpdate {
+            channel: 0,
+            sequence,
+            counts,
+        }
+    }
+
+    #[test]
+    fn test_receivers_skip_to_latest_report() {
+        let watch: ReportWatch<NoopRawMutex, 2> = Watch::new();
+        let mut receiver = ReportReceiver::new(watch.receiver().unwrap());
+        assert_eq!(receiver.try_changed(), None);
+
+        watch.sender().send(update(1, [1, 1]));
+        watch.sender().send(update(2, [2, 2]));
+        assert_eq!(receiver.try_changed(), Some(update(2, [2, 2])));
+        assert_eq!(receiver.try_changed(), None);
+
+        // Unchanged counts still make a new report
+        watch.sender().send(update(3, [2, 2]));
+        assert_eq!(receiver.try_changed(), Some(update(3, [2, 2])));
+    }
+
+    #[test]
+    fn test_min_delta_is_measured_from_last_report_returned() {
+        let watch: ReportWatch<NoopRawMutex, 2> = Watch::new();
+        let mut receiver = ReportReceiver::new(watch.receiver().unwrap()).with_min_delta(3);
+
+        watch.sender().send(update(1, [0, 10]));
+        assert_eq!(receiver.try_changed(), Some(update(1, [0, 10])));
+
+        // Small steps add up until one bucket has moved far enough
+        for (sequence, counts) in [(2, [1, 9]), (3, [2, 8])] {
+            watch.sender().send(update(sequence, counts));
+            assert_eq!(receiver.try_changed(), None);
+        }
+        watch.sender().send(update(4, [2, 7]));
+        assert_eq!(receiver.try_changed(), Some(update(4, [2, 7])));
+    }
+}
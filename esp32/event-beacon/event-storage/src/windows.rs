@@ -0,0 +1,316 @@
+use crate::history::{Block, History};
+
+/// Position of the oldest sample still inside a window.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Tail {
+    sequence: u64,
+    offset: u16,
+}
+
+/// Running High and total sample counts for a set of sliding windows.
+///
+/// Each window keeps its counts and a tail into the history. Recording a
+/// sample adds it to every count, then walks each tail forward past the
+/// samples that have aged out, subtracting them again. Every sample enters and leaves a
+/// window once, so the cost per record is constant no matter how large the
+/// windows or the history are.
+pub struct Windows<const WINDOWS: usize> {
+    breakpoints: [u64; WINDOWS],
+    counts: [u32; WINDOWS],
+    totals: [u32; WINDOWS],
+    tails: [Tail; WINDOWS],
+}
+
+impl<const WINDOWS: usize> Windows<WINDOWS> {
+    pub const fn new(breakpoints: [u64; WINDOWS]) -> Self {
+        Self {
+            breakpoints,
+            counts: [0; WINDOWS],
+            totals: [0; WINDOWS],
+            tails: [Tail {
+                sequence: 0,
+                offset: 0,
+            }; WINDOWS],
+        }
+    }
+
+    pub fn breakpoints(&self) -> &[u64; WINDOWS] {
+        &self.breakpoints
+    }
+
+    /// High counts for each window, saturated to fit the report.
+    pub fn counts(&self) -> [u16; WINDOWS] {
+        self.counts
+            .map(|count| u16::try_from(count).unwrap_or(u16::MAX))
+    }
+
+    /// Percentage of the samples in each window that were High, rounded down.
+    /// Empty windows read as zero.
+    pub fn duty_cycle(&self) -> [u8; WINDOWS] {
+        let mut duty_cycle = [0; WINDOWS];
+        for ((duty_cycle, count), total) in duty_cycle.iter_mut().zip(self.counts).zip(self.totals)
+        {
+            if total > 0 {
+                *duty_cycle = (count as u64 * 100 / total as u64) as u8;
+            }
+        }
+        duty_cycle
+    }
+
+    /// How much of each window `history` actually spans, in milliseconds.
+    ///
+    /// A window reaches further back than the oldest sample still in history
+    /// right after boot, or once its breakpoint outgrows the history, and
+    /// then covers less time than its breakpoint claims.
+    pub fn coverage<const CAPACITY: usize>(&self, history: &History<CAPACITY>) -> [u32; WINDOWS] {
+        let mut blocks = history.blocks();
+        let span = match (blocks.next(), blocks.next_back()) {
+            (Some(oldest), Some(newest)) => newest.last() - oldest.start,
+            (Some(only), None) => only.last() - only.start,
+            _ => 0,
+        };
+
+        self.breakpoints
+            .map(|breakpoint| u32::try_from(span.min(breakpoint)).unwrap_or(u32::MAX))
+    }
+
+    /// Recomputes every count and tail from scratch, as of `now`, after the
+    /// history has been replaced wholesale.
+    pub fn rebuild<const CAPACITY: usize>(&mut self, history: &History<CAPACITY>, now: u64) {
+        for (((breakpoint, count), total), tail) in self
+            .breakpoints
+            .iter()
+            .zip(self.counts.iter_mut())
+            .zip(self.totals.iter_mut())
+            .zip(self.tails.iter_mut())
+        {
+            let cutoff = (now + 1).saturating_sub(*breakpoint);
+            *count = history.count_high(now, *breakpoint);
+            *total = history.count_samples(now, *breakpoint);
+            *tail = Tail {
+                sequence: history.first_sequence(),
+                offset: 0,
+            };
+
+            // Same resting place `record` leaves the tail in: the first block with
+            // samples inside the window, or the end of the newest block
+            while let Some(block) = history.get(tail.sequence) {
+                tail.offset = block.count - block.count_since(cutoff);
+                if tail.offset < block.count || Some(tail.sequence) == history.last_sequence() {
+                    break;
+                }
+                tail.sequence += 1;
+            }
+        }
+    }
+
+    /// Accounts for a sample that has just been pushed onto `history`.
+    ///
+    /// `evicted` is the block that `History::push` dropped to make room, if any.
+    pub fn record<const CAPACITY: usize>(
+        &mut self,
+        history: &History<CAPACITY>,
+        evicted: Option<Block>,
+        now: u64,
+        high: bool,
+    ) {
+        let first_sequence = history.first_sequence();
+
+        for (((breakpoint, count), total), tail) in self
+            .breakpoints
+            .iter()
+            .zip(self.counts.iter_mut())
+            .zip(self.totals.iter_mut())
+            .zip(self.tails.iter_mut())
+        {
+            if let Some(block) = evicted
+                && tail.sequence < first_sequence
+            {
+                let remaining = tail.offset.min(block.count)..block.count;
+                *count -= block.count_high(remaining.clone()) as u32;
+                *total -= remaining.len() as u32;
+                *tail = Tail {
+                    sequence: first_sequence,
+                    offset: 0,
+                };
+            }
+
+            if high {
+                *count += 1;
+            }
+            *total += 1;
+
+            let cutoff = (now + 1).saturating_sub(*breakpoint);
+            while let Some(block) = history.get(tail.sequence) {
+                let expired = block.count - block.count_since(cutoff);
+                if expired > tail.offset {
+                    *count -= block.count_high(tail.offset..expired) as u32;
+                    *total -= (expired - tail.offset) as u32;
+                    tail.offset = expired;
+                }
+
+                if tail.offset < block.count || Some(tail.sequence) == history.last_sequence() {
+                    break;
+                }
+                *tail = Tail {
+                    sequence: tail.sequence + 1,
+                    offset: 0,
+                };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct XorShift(u64);
+
+    impl XorShift {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+    }
+
+    fn compare_against_rescan<const CAPACITY: usize>(seed: u64, samples: usize) {
+        let breakpoints = [0, 1, 100, 1000, 5000, 30000, 600000, u64::MAX];
+        let mut rng = XorShift(seed);
+        let mut history: History<CAPACITY> = History::new();
+        let mut windows = Windows::new(breakpoints);
+        let mut timestamp = 0;
+        let mut high = false;
+
+        for _ in 0..samples {
+            let gap = match rng.next() % 40 {
+                0 => rng.next() % 5000,
+                1..=4 => 90 + rng.next() % 20,
+                _ => 100,
+            };
+            if rng.next().is_multiple_of(15) {
+                high = !high;
+            }
+            timestamp = history.align(timestamp + gap, high);
+
+            let evicted = history.push(timestamp, high);
+            windows.record(&history, evicted, timestamp, high);
+
+            let rescanned = breakpoints.map(|breakpoint| {
+                u16::try_from(history.count_high(timestamp, breakpoint)).unwrap_or(u16::MAX)
+            });
+            assert_eq!(windows.counts(), rescanned);
+            assert_eq!(
+                windows.totals,
+                breakpoints.map(|breakpoint| history.count_samples(timestamp, breakpoint))
+            );
+        }
+    }
+
+    #[test]
+    fn test_incremental_counts_match_rescan_with_room_to_spare() {
+        compare_against_rescan::<1024>(0x9e37_79b9_7f4a_7c15, 20_000);
+    }
+
+    #[test]
+    fn test_incremental_counts_match_rescan_while_evicting() {
+        compare_against_rescan::<16>(0x2545_f491_4f6c_dd1d, 50_000);
+    }
+
+    #[test]
+    fn test_incremental_counts_match_rescan_with_single_run() {
+        compare_against_rescan::<1>(0xdead_beef_cafe_f00d, 20_000);
+    }
+
+    #[test]
+    fn test_rebuilt_counts_keep_matching_rescan() {
+        let breakpoints = [0, 100, 1000, 5000];
+        let mut rng = XorShift(0x0123_4567_89ab_cdef);
+        let mut history: History<32> = History::new();
+        let mut timestamp = 0;
+
+        for _ in 0..500 {
+            let high = rng.next().is_multiple_of(3);
+            timestamp = history.align(timestamp + 90 + rng.next() % 20, high);
+            history.push(timestamp, high);
+        }
+
+        let mut windows = Windows::new(breakpoints);
+        windows.rebuild(&history, timestamp);
+
+        for _ in 0..2000 {
+            let high = rng.next().is_multiple_of(3);
+            timestamp = history.align(timestamp + 90 + rng.next() % 20, high);
+            let evicted = history.push(timestamp, high);
+            windows.record(&history, evicted, timestamp, high);
+
+            let rescanned = breakpoints.map(|breakpoint| {
+                u16::try_from(history.count_high(timestamp, breakpoint)).unwrap_or(u16::MAX)
+            });
+            assert_eq!(windows.counts(), rescanned);
+            assert_eq!(
+                windows.totals,
+                breakpoints.map(|breakpoint| history.count_samples(timestamp, breakpoint))
+            );
+        }
+    }
+
+    #[test]
+    fn test_duty_cycle_is_share_of_high_samples() {
+        let mut history: History<8> = History::new();
+        let mut windows = Windows::new([250, 1000]);
+        assert_eq!(windows.duty_cycle(), [0, 0]);
+
+        for (timestamp, high) in [(0, true), (100, false), (200, false), (300, true)] {
+            let evicted = history.push(timestamp, high);
+            windows.record(&history, evicted, timestamp, high);
+        }
+
+        // 100..=300 holds three samples, one High; 0..=300 holds four, two High
+        assert_eq!(windows.duty_cycle(), [33, 50]);
+    }
+
+    #[test]
+    fn test_samples_age_out_of_windows() {
+        let mut history: History<8> = History::new();
+        let mut windows = Windows::new([1000, 5000]);
+
+        for (timestamp, high) in [(0, true), (100, true), (200, false)] {
+            let evicted = history.push(timestamp, high);
+            windows.record(&history, evicted, timestamp, high);
+        }
+        assert_eq!(windows.counts(), [2, 2]);
+
+        let evicted = history.push(1050, false);
+        windows.record(&history, evicted, 1050, false);
+        assert_eq!(windows.counts(), [1, 2]);
+
+        let evicted = history.push(5100, false);
+        windows.record(&history, evicted, 5100, false);
+        assert_eq!(windows.counts(), [0, 0]);
+    }
+
+    #[test]
+    fn test_coverage_is_limited_by_history() {
+        let mut history: History<2> = History::new();
+        let windows = Windows::new([1000, 10_000]);
+        assert_eq!(windows.coverage(&history), [0, 0]);
+
+        // Right after boot neither window is covered yet
+        history.push(0, true);
+        history.push(100, true);
+        assert_eq!(windows.coverage(&history), [100, 100]);
+
+        // Once the oldest block is evicted the longer window stays short
+        history.push(200, false);
+        history.push(2000, true);
+        history.push(8000, true);
+        history.push(8100, false);
+        history.push(8200, false);
+        assert_eq!(windows.coverage(&history), [1000, 8200]);
+        history.push(9000, true);
+        assert_eq!(windows.coverage(&history), [1000, 7000]);
+    }
+}